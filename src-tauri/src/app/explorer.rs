@@ -11,7 +11,7 @@ use crate::service::explorer_state::{
 };
 use crate::service::explorer_types::SortConfig;
 use crate::service::types::ActiveTab;
-use crate::utils::file_utils::find_first_image_in_folder;
+use crate::utils::file_utils::{find_thumbnail_image, is_compressed_file};
 use crate::utils::watcher_utils::{
     create_explorer_watcher_callback, subscribe_directory, unsubscribe_directory,
 };
@@ -26,8 +26,20 @@ pub(crate) async fn transfer_folder(
 ) -> Result<(), String> {
     // spawn_blocking でファイル移動（同期 I/O）を実行
     tokio::task::spawn_blocking(move || {
-        let options = CopyOptions::new();
-        move_dir(from, to, &options).map_err(|e| format!("failed to move folder: {e}"))
+        let from_path = std::path::Path::new(&from);
+        if from_path.is_file() {
+            // アーカイブファイルはフォルダと同様に移動先ディレクトリの直下へ移動する
+            let dest = std::path::Path::new(&to).join(from_path.file_name().unwrap_or_default());
+            let options = fs_extra::file::CopyOptions::new();
+            fs_extra::file::move_file(&from, dest, &options)
+                .map(|_| ())
+                .map_err(|e| format!("failed to move file: {e}"))
+        } else {
+            let options = CopyOptions::new();
+            move_dir(from, to, &options)
+                .map(|_| ())
+                .map_err(|e| format!("failed to move folder: {e}"))
+        }
     })
     .await
     .map_err(|e| format!("Failed to spawn blocking task: {}", e))??;
@@ -44,6 +56,7 @@ pub(crate) async fn transfer_folder(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -187,6 +200,7 @@ pub(crate) async fn change_explorer_path(
         1,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -302,6 +316,7 @@ pub(crate) async fn change_explorer_page(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -339,6 +354,7 @@ pub(crate) async fn move_explorer_forward(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -374,6 +390,7 @@ pub(crate) async fn move_explorer_backward(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -397,6 +414,7 @@ pub(crate) async fn move_explorer_to_end(
         1,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -408,6 +426,7 @@ pub(crate) async fn move_explorer_to_end(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -433,6 +452,7 @@ pub(crate) async fn move_explorer_to_start(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -490,6 +510,7 @@ pub(crate) async fn refresh_explorer_tab(
         page,
         state.thumbnail_cache.clone(),
         state.dir_list_cache.clone(),
+        state.archive_options.clone(),
         &sort,
         search_query.as_deref(),
         Some(state.db.clone()),
@@ -531,6 +552,7 @@ pub(crate) async fn change_explorer_sort(
             1,
             state.thumbnail_cache.clone(),
             state.dir_list_cache.clone(),
+            state.archive_options.clone(),
             &sort,
             search_query.as_deref(),
             Some(state.db.clone()),
@@ -576,6 +598,7 @@ pub(crate) async fn change_explorer_search(
             1,
             state.thumbnail_cache.clone(),
             state.dir_list_cache.clone(),
+            state.archive_options.clone(),
             &sort,
             query.as_deref(),
             Some(state.db.clone()),
@@ -590,7 +613,7 @@ pub(crate) async fn change_explorer_search(
 }
 
 /// リコメンドを再構築する（バックグラウンド処理）
-/// 指定ディレクトリ配下のすべてのフォルダのサムネイルから埋め込みを生成する
+/// 指定ディレクトリ配下のすべてのフォルダ・アーカイブのサムネイルから埋め込みを生成する
 /// force_rebuild=false の場合、フォルダの更新日時が変わっていないものはスキップする
 #[tauri::command]
 pub(crate) async fn rebuild_recommendations(
@@ -622,7 +645,10 @@ pub(crate) async fn rebuild_recommendations(
     let dirs = read_dir(&directory_path).map_err(|_| "failed to open directory")?;
     let folder_entries: Vec<(String, i64)> = dirs
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_dir())
+        .filter(|e| {
+            let path = e.path();
+            path.is_dir() || is_compressed_file(path.to_str().unwrap_or_default())
+        })
         .filter_map(|e| {
             let path = e.path().to_str()?.to_string();
             let modified = e
@@ -645,7 +671,7 @@ pub(crate) async fn rebuild_recommendations(
     let folder_paths: Vec<String> = folder_entries.iter().map(|(p, _)| p.clone()).collect();
     let db_modified_map = state
         .db
-        .get_folder_modified_at_batch(&folder_paths)
+        .get_folder_modified_at_batch(&folder_paths, EMBEDDING_VERSION)
         .map_err(|e| e.to_string())?;
 
     // 処理開始
@@ -656,6 +682,7 @@ pub(crate) async fn rebuild_recommendations(
 
     // バックグラウンドで処理
     let db = state.db.clone();
+    let archive_options = state.archive_options.read().await.clone();
     let service = embedding_service.clone();
     let app_handle = app.clone();
 
@@ -712,14 +739,19 @@ pub(crate) async fn rebuild_recommendations(
                 let app_handle = app_handle.clone();
                 let processed = processed.clone();
                 let skipped_error = skipped_error.clone();
+                let archive_options = archive_options.clone();
 
                 let handle = tokio::spawn(async move {
                     // セマフォを取得（同時実行数を制限）
                     let _permit = sem.acquire().await.unwrap();
 
-                    // フォルダの最初の画像を取得
-                    let thumbnail_path =
-                        find_first_image_in_folder(std::path::Path::new(&folder_path));
+                    // フォルダの最初の画像（アーカイブの場合は表紙画像）を取得
+                    let path = folder_path.clone();
+                    let thumbnail_path = tokio::task::spawn_blocking(move || {
+                        find_thumbnail_image(std::path::Path::new(&path), &archive_options)
+                    })
+                    .await
+                    .unwrap_or_default();
 
                     if thumbnail_path.is_empty() {
                         skipped_error.fetch_add(1, Ordering::Relaxed);
@@ -2,6 +2,7 @@
 pub mod viewer;
 #[macro_use]
 pub mod explorer;
+mod protocol;
 
 use tauri::{
     async_runtime::Mutex,
@@ -24,11 +25,16 @@ use crate::{
         },
         viewer::{
             change_active_viewer, change_active_viewer_tab, change_viewing,
-            close_viewer_tabs_by_directory, get_active_viewer_directory, get_filenames_inner_zip,
-            move_backward, move_forward, open_file_image, open_image_dialog, open_new_viewer,
-            open_new_viewer_tab, read_image_in_zip, record_folder_view, refresh_viewer_tab_tree,
-            remove_viewer_tab, request_restore_viewer_state, request_restore_viewer_tab_state,
-            subscribe_dir_notification, unsubscribe_dir_notification,
+            close_viewer_tabs_by_directory, expand_viewer_directory, export_animation_frame,
+            get_active_viewer_directory, get_animation_info, get_color_management,
+            get_filenames_inner_zip, get_format_settings, get_image_metadata, get_image_tile_info,
+            get_neighbor_images, move_backward, move_forward, move_to_next_folder,
+            move_to_prev_folder, open_image_dialog, open_new_viewer, open_new_viewer_tab,
+            record_folder_view, refresh_viewer_tab_tree, remove_viewer_tab,
+            request_restore_viewer_state, request_restore_viewer_tab_state,
+            set_archive_name_encoding, set_archive_password, set_color_management,
+            set_format_settings, set_navigation_mode, subscribe_dir_notification,
+            unsubscribe_dir_notification,
         },
     },
     service::{
@@ -39,6 +45,9 @@ use crate::{
         model_downloader,
         viewer_state::{add_viewer_state, add_viewer_tab_state, remove_viewer_state, ViewerState},
     },
+    utils::color_utils::{self, ColorManagement},
+    utils::format_registry::{self, FormatSettings},
+    utils::tile_utils::TileCache,
 };
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -47,6 +56,12 @@ struct SavedState {
     active: ActiveViewer,
     viewers: Vec<ViewerState>,
     explorers: Vec<ExplorerState>,
+    /// ユーザーが追加した画像・動画の拡張子
+    #[serde(default)]
+    format_settings: FormatSettings,
+    /// 埋め込みの ICC プロファイルの扱い
+    #[serde(default)]
+    color_management: ColorManagement,
 }
 
 impl Default for SavedState {
@@ -61,8 +76,11 @@ impl Default for SavedState {
                 count: 0,
                 active: None,
                 tabs: vec![],
+                navigation_mode: Default::default(),
             }],
             explorers: vec![],
+            format_settings: FormatSettings::default(),
+            color_management: ColorManagement::default(),
         }
     }
 }
@@ -103,6 +121,8 @@ pub fn create_viewer() -> Builder<Wry> {
     } else {
         SavedState::default()
     };
+    format_registry::set_format_settings(saved_state.format_settings.clone());
+    color_utils::set_color_management(saved_state.color_management);
 
     // Initialize SQLite database (Phase 2)
     let db_path = app_dir.join("data.db");
@@ -121,6 +141,11 @@ pub fn create_viewer() -> Builder<Wry> {
         dir_list_cache: std::sync::Arc::new(tokio::sync::RwLock::new(
             std::collections::HashMap::new(),
         )),
+        archive_options: std::sync::Arc::new(tokio::sync::RwLock::new(Default::default())),
+        archive_cache: std::sync::Arc::new(Default::default()),
+        media_cache: std::sync::Arc::new(Default::default()),
+        tile_cache: std::sync::Arc::new(TileCache::new(app_dir.join("tiles"))),
+        animation_cache: std::sync::Arc::new(Default::default()),
         db: std::sync::Arc::new(db),
         embedding_service: tokio::sync::RwLock::new(None),
     };
@@ -169,6 +194,10 @@ pub fn create_viewer() -> Builder<Wry> {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
+        .register_asynchronous_uri_scheme_protocol(
+            protocol::MEDIA_PROTOCOL,
+            protocol::handle_media_request,
+        )
         .setup(move |app| {
             // Setup menu
             let quit_item = MenuItemBuilder::with_id("quit", "Quit").build(app)?;
@@ -298,6 +327,8 @@ pub fn create_viewer() -> Builder<Wry> {
                         active,
                         viewers,
                         explorers,
+                        format_settings: format_registry::format_settings(),
+                        color_management: color_utils::color_management(),
                     };
                     let dir = dirs::data_dir().unwrap_or_default();
                     let app_dir = dir.join(get_app_dir_name());
@@ -328,9 +359,17 @@ pub fn create_viewer() -> Builder<Wry> {
         })
         .manage(app_state)
         .invoke_handler(tauri::generate_handler![
-            open_file_image,
             get_filenames_inner_zip,
-            read_image_in_zip,
+            get_animation_info,
+            export_animation_frame,
+            get_image_metadata,
+            get_image_tile_info,
+            set_archive_name_encoding,
+            set_archive_password,
+            get_format_settings,
+            set_format_settings,
+            get_color_management,
+            set_color_management,
             subscribe_dir_notification,
             unsubscribe_dir_notification,
             open_new_viewer,
@@ -361,10 +400,15 @@ pub fn create_viewer() -> Builder<Wry> {
             unsubscribe_explorer_dir_notification,
             refresh_explorer_tab,
             change_viewing,
+            get_neighbor_images,
             move_forward,
+            set_navigation_mode,
+            move_to_next_folder,
+            move_to_prev_folder,
             move_backward,
             request_restore_viewer_tab_state,
             refresh_viewer_tab_tree,
+            expand_viewer_directory,
             get_active_viewer_directory,
             close_viewer_tabs_by_directory,
             record_folder_view,
@@ -0,0 +1,408 @@
+//! 画像・動画のバイト列を配信するカスタム URI スキーム
+//!
+//! Base64 に変換してコマンドで返すとデータ量が 1/3 増え、大きな画像ではエンコードの間待たされる。
+//! ファイルやアーカイブ内のエントリの生データを Content-Type 付きで返し、
+//! フロントエンドからは通常の `<img>` の URL として参照できるようにする
+//!
+//! URL は `siv://localhost/<パス>?entry=<エントリ名>&max=<長辺の最大値>` の形式（Windows では `https://siv.localhost/...`）で、
+//! フロントエンドでは `convertFileSrc(path, 'siv')` で組み立てる。アーカイブ内のエントリでなければ `entry` は省く
+//! `max` を指定すると、それより大きな画像は縮小した表示用の画像を返す（省くと元の解像度の画像）
+//! `tile=<レベル>/<x>/<y>` を指定すると、拡大表示用のタイルを返す（`tile_utils` を参照）
+//! `frame=<番号>` を指定すると、アニメーション画像のフレームを PNG で返す（`animation_utils` を参照）
+//!
+//! 動画のシークのため `Range` リクエストには部分応答 (206) を返す。ファイルと無圧縮で格納された
+//! アーカイブ内のエントリは要求された範囲だけを読み込み、圧縮されたエントリは一度スプールへ展開して
+//! 以降のリクエストではそこから読み込む
+
+use percent_encoding::percent_decode_str;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tauri::http::{header, Request, Response, StatusCode, Uri};
+use tauri::{AppHandle, Manager, UriSchemeContext, UriSchemeResponder, Wry};
+
+use crate::service::app_state::AppState;
+use crate::utils::archive::{
+    find_password_error, root_archive_path, stored_or_spooled_entry, StoredEntry,
+};
+use crate::utils::color_utils::{color_management, ColorManagement};
+use crate::utils::format_registry::{detect_mime_type, mime_type_from_path};
+use crate::utils::tile_utils::{image_source_key, TileRangeError};
+
+/// スキーム名
+pub(crate) const MEDIA_PROTOCOL: &str = "siv";
+
+/// 終端を指定しない範囲リクエスト (`bytes=0-`) に一度に返す最大サイズ
+/// 動画の再生開始時にファイル全体を読み込まないよう、続きはブラウザに改めて要求させる
+const MAX_OPEN_RANGE_LENGTH: u64 = 4 * 1024 * 1024;
+
+/// リクエストされたファイル
+struct MediaRequest {
+    /// ファイル（またはアーカイブ）のパス
+    path: String,
+    /// アーカイブ内のエントリ名
+    entry: Option<String>,
+    /// 表示用に縮小する場合の長辺の最大値
+    max_size: Option<u32>,
+    /// 拡大表示用のタイル (レベル, x, y)
+    tile: Option<(u32, u32, u32)>,
+    /// アニメーション画像のフレーム番号（0 始まり）
+    frame: Option<u32>,
+}
+
+impl MediaRequest {
+    fn parse(uri: &Uri) -> Option<Self> {
+        let decode = |s: &str| {
+            percent_decode_str(s)
+                .decode_utf8()
+                .ok()
+                .map(|s| s.into_owned())
+        };
+        let path = decode(uri.path().strip_prefix('/').unwrap_or(uri.path()))?;
+        if path.is_empty() {
+            return None;
+        }
+        // クエリはフォーム形式 (URLSearchParams) でエンコードされるため、`+` は空白として扱う
+        let query_param = |name: &str| {
+            uri.query()
+                .unwrap_or_default()
+                .split('&')
+                .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+                .and_then(|v| decode(&v.replace('+', " ")))
+        };
+        Some(Self {
+            path,
+            entry: query_param("entry"),
+            max_size: query_param("max")
+                .and_then(|v| v.parse().ok())
+                .filter(|&v| v > 0),
+            tile: query_param("tile").and_then(|v| {
+                let mut values = v.split('/').map(|n| n.parse().ok());
+                Some((values.next()??, values.next()??, values.next()??))
+            }),
+            frame: query_param("frame").and_then(|v| v.parse().ok()),
+        })
+    }
+
+    /// Content-Type の判定に使う名前
+    fn file_name(&self) -> &str {
+        self.entry.as_deref().unwrap_or(&self.path)
+    }
+}
+
+/// 実ファイル上の範囲（ファイル、無圧縮のエントリ、またはスプールへ展開したエントリ）の
+/// `start` から `length` バイトを読み込む
+fn read_stored(stored: &StoredEntry, start: u64, length: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(&stored.path)?;
+    file.seek(SeekFrom::Start(stored.offset + start))?;
+    let mut buf = Vec::with_capacity(length as usize);
+    file.take(length).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// カスタム URI スキームのリクエストを処理する
+/// 読み込みはブロッキング I/O のため、別スレッドで行ってから応答する
+pub(crate) fn handle_media_request(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app = ctx.app_handle().clone();
+    tauri::async_runtime::spawn(async move {
+        let mut response = match MediaRequest::parse(request.uri()) {
+            Some(media) => respond_media(&app, &request, media).await,
+            None => error_response(StatusCode::BAD_REQUEST, "invalid media url".to_string()),
+        };
+        // フロントエンドから fetch で読み込めるよう、アプリ自身のオリジンにだけ読み取りを許可する
+        if let Some(origin) = allowed_origin(&request) {
+            let headers = response.headers_mut();
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(header::VARY, header::HeaderValue::from_static("Origin"));
+        }
+        responder.respond(response);
+    });
+}
+
+async fn respond_media(
+    app: &AppHandle,
+    request: &Request<Vec<u8>>,
+    media: MediaRequest,
+) -> Response<Vec<u8>> {
+    // ファイルが変わらない限り同じ URL の内容は変わらないため、ブラウザのキャッシュを再検証で使い回す
+    let etag = entity_tag(root_archive_path(&media.path));
+    if let Some(etag) = &etag {
+        let if_none_match = request
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok());
+        if if_none_match == Some(etag.as_str()) {
+            return Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(vec![])
+                .unwrap_or_default();
+        }
+    }
+
+    let file_name = media.file_name().to_string();
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    // 範囲指定のない読み込みは表示用の縮小や形式の変換が行われうる
+    let may_be_converted = range.is_none();
+    let (status, content_range, data) = match read_media(app, media, range).await {
+        Ok(result) => result,
+        Err((status, message)) => return error_response(status, message),
+    };
+    // 表示用に縮小・変換した画像やタイルは元の形式と異なる形式でエンコードされるため、内容から判定する
+    // 拡張子で判定できないファイルは先頭から読み込んだ場合だけ内容から判定する
+    let is_head = content_range
+        .as_deref()
+        .map_or(true, |range| range.starts_with("bytes 0-"));
+    let content_type = match image::guess_format(&data) {
+        Ok(format) if may_be_converted => format.to_mime_type(),
+        _ if is_head => detect_mime_type(&file_name, &data),
+        _ => mime_type_from_path(&file_name).unwrap_or("application/octet-stream"),
+    };
+    let mut builder = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, data.len())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "no-cache");
+    if let Some(content_range) = content_range {
+        builder = builder.header(header::CONTENT_RANGE, content_range);
+    }
+    if let Some(etag) = etag {
+        builder = builder.header(header::ETAG, etag);
+    }
+    builder.body(data).unwrap_or_default()
+}
+
+/// ファイル（またはアーカイブ内のエントリ）の内容を読み込む
+/// `range` が指定されていればその範囲だけを読み込み、ステータス・Content-Range と共に返す
+/// 範囲がデータの外であれば 416 と `Content-Range: bytes */<サイズ>` を返す
+/// 失敗した場合は応答するステータスとメッセージを返す
+async fn read_media(
+    app: &AppHandle,
+    media: MediaRequest,
+    range: Option<String>,
+) -> Result<(StatusCode, Option<String>, Vec<u8>), (StatusCode, String)> {
+    let state = app.state::<AppState>();
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let media_cache = state.media_cache.clone();
+    let tile_cache = state.tile_cache.clone();
+    let animation_cache = state.animation_cache.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Some(frame) = media.frame {
+            let entry = media.entry.as_deref();
+            let key = image_source_key(&media.path, entry);
+            let data = animation_cache
+                .get_frame_png(&key, frame, || {
+                    media_cache.load(&media.path, entry, &archive_options, &archive_cache)
+                })
+                .map_err(|e| load_error_status(&e))?;
+            return Ok((StatusCode::OK, None, data));
+        }
+
+        if let Some((level, x, y)) = media.tile {
+            let entry = media.entry.as_deref();
+            let key = image_source_key(&media.path, entry);
+            let data = tile_cache
+                .get_tile(&key, level, x, y, || {
+                    media_cache.load(&media.path, entry, &archive_options, &archive_cache)
+                })
+                .map_err(|e| match e.downcast_ref::<TileRangeError>() {
+                    Some(range_error) => (StatusCode::BAD_REQUEST, range_error.to_string()),
+                    None => load_error_status(&e),
+                })?;
+            return Ok((StatusCode::OK, None, data));
+        }
+
+        let Some(range) = range else {
+            // 範囲指定のない読み込み（画像）は先読み済みのデータがあればそれを返す
+            // WebView が表示できない形式は表示できる形式に変換する
+            let entry = media.entry.as_deref();
+            let data = match media.max_size {
+                Some(max_size) => media_cache.load_display(
+                    &media.path,
+                    entry,
+                    max_size,
+                    &archive_options,
+                    &archive_cache,
+                ),
+                None => {
+                    media_cache.load_viewable(&media.path, entry, &archive_options, &archive_cache)
+                }
+            }
+            .map_err(|e| load_error_status(&e))?;
+            return Ok((StatusCode::OK, None, Arc::unwrap_or_clone(data)));
+        };
+
+        let stored = match media.entry {
+            Some(entry) => archive_cache
+                .with_archive(&media.path, &archive_options, |archive| {
+                    stored_or_spooled_entry(archive, &media.path, &entry)
+                })
+                .map_err(|e| load_error_status(&e))?,
+            None => {
+                let metadata = std::fs::metadata(&media.path).map_err(io_error_status)?;
+                StoredEntry {
+                    path: PathBuf::from(&media.path),
+                    offset: 0,
+                    size: metadata.len(),
+                    spool: None,
+                }
+            }
+        };
+
+        let size = stored.size;
+        let Some((start, end)) = parse_range(&range, size) else {
+            let content_range = format!("bytes */{}", size);
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                Some(content_range),
+                vec![],
+            ));
+        };
+        let data = read_stored(&stored, start, end - start + 1).map_err(io_error_status)?;
+        let content_range = format!("bytes {}-{}/{}", start, end, size);
+        Ok((StatusCode::PARTIAL_CONTENT, Some(content_range), data))
+    })
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to read file: {}", e),
+        )
+    })?
+}
+
+/// Range ヘッダ (`bytes=start-end` / `bytes=start-` / `bytes=-suffix`) を解釈し、
+/// 読み込む範囲 (start, end)（end を含む）を返す。複数範囲の指定には対応しない
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+    if size == 0 || range.contains(',') {
+        return None;
+    }
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (start, "") => {
+            let start: u64 = start.parse().ok()?;
+            (
+                start,
+                start
+                    .saturating_add(MAX_OPEN_RANGE_LENGTH - 1)
+                    .min(size - 1),
+            )
+        }
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(size - 1)),
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// リクエスト元がアプリ自身のページであれば、その Origin ヘッダの値を返す
+/// 本番では `tauri://localhost`（Windows では `https://tauri.localhost`）、開発時は devUrl から読み込まれる
+fn allowed_origin(request: &Request<Vec<u8>>) -> Option<header::HeaderValue> {
+    const APP_ORIGINS: [&str; 3] = [
+        "tauri://localhost",
+        "https://tauri.localhost",
+        "http://tauri.localhost",
+    ];
+    const DEV_ORIGIN: &str = "http://localhost:1420";
+    let origin = request.headers().get(header::ORIGIN)?;
+    let value = origin.to_str().ok()?;
+    let allowed = APP_ORIGINS.contains(&value) || (cfg!(debug_assertions) && value == DEV_ORIGIN);
+    allowed.then(|| origin.clone())
+}
+
+/// 読み込みエラーを応答するステータスとメッセージにする
+fn load_error_status(e: &anyhow::Error) -> (StatusCode, String) {
+    if let Some(password_error) = find_password_error(e) {
+        // フロントエンドでパスワード入力を促せるよう、パスワード関連のエラーは本文にそのまま返す
+        return (StatusCode::UNAUTHORIZED, password_error.to_string());
+    }
+    (
+        StatusCode::NOT_FOUND,
+        format!("failed to read file: {:#}", e),
+    )
+}
+
+fn io_error_status(e: std::io::Error) -> (StatusCode, String) {
+    let status = if e.kind() == std::io::ErrorKind::NotFound {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (status, format!("failed to read file: {}", e))
+}
+
+/// ファイルのサイズと更新日時から ETag を作る
+/// 変換した画像の色は ICC プロファイルの扱いの設定で変わるため、設定も含める
+fn entity_tag(path: &str) -> Option<String> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    let color = match color_management() {
+        ColorManagement::ConvertToSrgb => "srgb",
+        ColorManagement::PassThrough => "raw",
+    };
+    Some(format!(
+        "\"{:x}-{:x}-{}\"",
+        metadata.len(),
+        modified.as_nanos(),
+        color
+    ))
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(message.into_bytes())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(
+            parse_range("bytes=0-", 100 * 1024 * 1024),
+            Some((0, MAX_OPEN_RANGE_LENGTH - 1))
+        );
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=18446744073709551615-", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let request = |origin: &str| {
+            Request::builder()
+                .header(header::ORIGIN, origin)
+                .body(vec![])
+                .unwrap()
+        };
+        assert!(allowed_origin(&request("tauri://localhost")).is_some());
+        assert!(allowed_origin(&request("https://tauri.localhost")).is_some());
+        assert!(allowed_origin(&request("https://example.com")).is_none());
+        assert!(allowed_origin(&Request::new(vec![])).is_none());
+    }
+}
@@ -1,16 +1,22 @@
-use base64::{engine::general_purpose, Engine as _};
 use notify::RecursiveMode;
-use std::fs::File as StdFile;
-use std::io::{BufReader, Read};
 use tauri::{AppHandle, Emitter, State, WebviewWindow};
 
 use crate::service::app_state::{open_file_pick_dialog, ActiveTab, ActiveViewer, AppState};
 use crate::service::viewer_state::{
-    add_viewer_state, add_viewer_tab_state, find_key_in_tree, get_next_in_tree, get_prev_in_tree,
-    rebuild_file_tree, remove_viewer_tab_state, File,
+    add_viewer_state, add_viewer_tab_state, build_lazy_directory, expand_lazy_directories_on_step,
+    expand_viewer_tab_directory, expanded_archive_paths, find_file_in_tree, find_key_in_tree,
+    get_next_file, get_prev_file, move_viewer_tab_to_sibling, neighbor_images, prefetch_neighbors,
+    rebuild_file_tree, remove_viewer_tab_state, set_lazy_directory_children, File, FileType,
+    NavigationMode,
 };
 
-use crate::utils::file_utils::normalize_path;
+use crate::utils::animation_utils::AnimationInfo;
+use crate::utils::archive::{list_archive_file_names, root_archive_path, NameEncoding};
+use crate::utils::color_utils::{self, ColorManagement};
+use crate::utils::file_utils::{find_thumbnail_image, is_compressed_file, normalize_path};
+use crate::utils::format_registry::{self, FormatSettings};
+use crate::utils::metadata_utils::{read_image_metadata, ImageMetadata};
+use crate::utils::tile_utils::{image_source_key, read_tile_info, TileInfo};
 use crate::utils::watcher_utils::{
     create_viewer_watcher_callback, subscribe_directory, unsubscribe_directory,
 };
@@ -50,7 +56,8 @@ pub(crate) async fn subscribe_dir_notification(
     state: State<'_, AppState>,
     app: AppHandle,
 ) -> Result<(), String> {
-    let callback = create_viewer_watcher_callback(app, filepath.clone());
+    let callback =
+        create_viewer_watcher_callback(app, filepath.clone(), state.archive_cache.clone());
 
     subscribe_directory(filepath, &state, RecursiveMode::Recursive, callback).await
 }
@@ -75,7 +82,7 @@ pub(crate) async fn refresh_viewer_tab_tree(
     app: AppHandle,
 ) -> Result<(), String> {
     // ロックを短時間だけ保持して必要な情報を取得する
-    let (path, is_compressed, current_key) = {
+    let (path, is_compressed, current_viewing, expanded_paths) = {
         let viewers = state.viewers.lock().await;
         let viewer_state = (*viewers)
             .iter()
@@ -89,22 +96,34 @@ pub(crate) async fn refresh_viewer_tab_tree(
         let is_compressed = tab_state
             .viewing
             .as_ref()
-            .map(|v| v.file_type == "Zip")
+            .map(|v| v.file_type == FileType::Zip)
             .unwrap_or(false);
-        let current_key = tab_state.viewing.as_ref().map(|v| v.key.clone());
-        (tab_state.path.clone(), is_compressed, current_key)
+        (
+            tab_state.path.clone(),
+            is_compressed,
+            tab_state.viewing.clone(),
+            expanded_archive_paths(&tab_state.tree),
+        )
     }; // ロック解放
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
 
     // ファイルツリー再構築をロック外のブロッキングスレッドで実行
-    let new_tree = tokio::task::spawn_blocking(move || rebuild_file_tree(&path, is_compressed))
-        .await
-        .map_err(|e| format!("Failed to rebuild file tree: {}", e))?;
+    // 開いていた内側のアーカイブは展開し直す
+    let new_tree = tokio::task::spawn_blocking(move || {
+        let mut tree = rebuild_file_tree(&path, is_compressed, &archive_options, &archive_cache);
+        for lazy_path in expanded_paths {
+            let children =
+                build_lazy_directory(&path, &lazy_path, &archive_options, &archive_cache);
+            set_lazy_directory_children(&mut tree, &lazy_path, children);
+        }
+        tree
+    })
+    .await
+    .map_err(|e| format!("Failed to rebuild file tree: {}", e))?;
 
-    let new_viewing = if let Some(key) = current_key {
-        find_key_in_tree(&new_tree, &key)
-    } else {
-        None
-    };
+    // キーは振り直されるため、表示中のファイルはパスと名前で探す
+    let new_viewing = current_viewing.and_then(|viewing| find_file_in_tree(&new_tree, &viewing));
 
     // ロックを再取得して状態を更新する
     let mut viewers = state.viewers.lock().await;
@@ -128,38 +147,213 @@ pub(crate) async fn refresh_viewer_tab_tree(
     Ok(())
 }
 
+/// アーカイブ内のファイル名一覧を取得
 #[tauri::command]
-pub(crate) fn open_file_image(filepath: String) -> Result<String, String> {
-    let img = std::fs::read(&filepath).map_err(|e| format!("failed to read image: {}", e))?;
-    Ok(general_purpose::STANDARD_NO_PAD.encode(img))
+pub(crate) async fn get_filenames_inner_zip(
+    filepath: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let mut files = tokio::task::spawn_blocking(move || {
+        archive_cache.with_archive(&filepath, &archive_options, |archive| {
+            Ok(list_archive_file_names(archive))
+        })
+    })
+    .await
+    .map_err(|e| format!("failed to read archive: {}", e))?
+    .map_err(|e| format!("failed to read archive: {:#}", e))?;
+    files.sort_by(|a, b| natord::compare(a, b));
+    Ok(files)
 }
 
-/// ZIPファイル内のファイル名一覧を取得（ストリーミング読み込み）
+/// 画像のタイル分割の情報を取得する（`entry` はアーカイブ内の画像の場合に指定する）
+/// タイルはカスタム URI スキームに `tile=<レベル>/<x>/<y>` を指定して取得する
 #[tauri::command]
-pub(crate) fn get_filenames_inner_zip(filepath: String) -> Result<Vec<String>, String> {
-    let file = StdFile::open(&filepath).map_err(|e| format!("failed to open zip: {}", e))?;
-    let reader = BufReader::new(file);
-    let zip = zip::ZipArchive::new(reader).map_err(|e| format!("failed to read zip: {}", e))?;
-    let mut files: Vec<String> = zip.file_names().map(|s| s.into()).collect();
-    files.sort();
-    Ok(files)
+pub(crate) async fn get_image_tile_info(
+    path: String,
+    entry: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<TileInfo, String> {
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let media_cache = state.media_cache.clone();
+    tokio::task::spawn_blocking(move || {
+        let data = media_cache.load(&path, entry.as_deref(), &archive_options, &archive_cache)?;
+        read_tile_info(&data)
+    })
+    .await
+    .map_err(|e| format!("failed to read image: {}", e))?
+    .map_err(|e| format!("failed to read image: {:#}", e))
+}
+
+/// 画像のメタデータ（EXIF / IPTC / XMP と大きさ・ファイルサイズ・形式）を取得する
+/// `entry` はアーカイブ内の画像の場合に指定する
+#[tauri::command]
+pub(crate) async fn get_image_metadata(
+    path: String,
+    entry: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ImageMetadata, String> {
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let media_cache = state.media_cache.clone();
+    tokio::task::spawn_blocking(move || {
+        let data = media_cache.load(&path, entry.as_deref(), &archive_options, &archive_cache)?;
+        read_image_metadata(&data, entry.as_deref().unwrap_or(&path))
+    })
+    .await
+    .map_err(|e| format!("failed to read image metadata: {}", e))?
+    .map_err(|e| format!("failed to read image metadata: {:#}", e))
+}
+
+/// アニメーション画像のフレーム数・各フレームの表示時間・再生回数を取得する
+/// 各フレームはカスタム URI スキームに `frame=<番号>` を指定して取得する
+#[tauri::command]
+pub(crate) async fn get_animation_info(
+    path: String,
+    entry: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<AnimationInfo, String> {
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let media_cache = state.media_cache.clone();
+    let animation_cache = state.animation_cache.clone();
+    tokio::task::spawn_blocking(move || {
+        let entry = entry.as_deref();
+        animation_cache.get_info(&image_source_key(&path, entry), || {
+            media_cache.load(&path, entry, &archive_options, &archive_cache)
+        })
+    })
+    .await
+    .map_err(|e| format!("failed to read animation: {}", e))?
+    .map_err(|e| format!("failed to read animation: {:#}", e))
+}
+
+/// アニメーション画像のフレーム（0 始まり）を PNG ファイルに書き出す
+#[tauri::command]
+pub(crate) async fn export_animation_frame(
+    path: String,
+    entry: Option<String>,
+    frame: u32,
+    dest_path: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let media_cache = state.media_cache.clone();
+    let animation_cache = state.animation_cache.clone();
+    tokio::task::spawn_blocking(move || {
+        let entry = entry.as_deref();
+        let data = animation_cache.get_frame_png(&image_source_key(&path, entry), frame, || {
+            media_cache.load(&path, entry, &archive_options, &archive_cache)
+        })?;
+        std::fs::write(&dest_path, data)?;
+        Ok::<_, anyhow::Error>(())
+    })
+    .await
+    .map_err(|e| format!("failed to export frame: {}", e))?
+    .map_err(|e| format!("failed to export frame: {:#}", e))
+}
+
+/// 暗号化されたアーカイブのパスワードを設定する（セッション中のみ保持）
+/// 内側のアーカイブのサブツリーを展開し直すため、対象のタブのファイルツリーも再構築する
+#[tauri::command]
+pub(crate) async fn set_archive_password(
+    path: String,
+    password: String,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    state
+        .archive_options
+        .write()
+        .await
+        .passwords
+        .insert(path.clone(), password);
+    // パスワードなしで開いたハンドルを使わないよう破棄する
+    state.archive_cache.invalidate(root_archive_path(&path));
+    refresh_viewer_tabs_for_archive(&path, &state, &app).await
 }
 
-/// ZIP内の画像をBase64で読み込み（ストリーミング読み込み）
+/// アーカイブ内のファイル名の文字コードを指定する（None で自動判定に戻す）
+/// 指定したアーカイブを開いているタブのファイルツリーは新しい文字コードで再構築する
 #[tauri::command]
-pub(crate) fn read_image_in_zip(path: String, filename: String) -> Result<String, String> {
-    let file = StdFile::open(&path).map_err(|e| format!("failed to open zip: {}", e))?;
-    let reader = BufReader::new(file);
-    let mut zip = zip::ZipArchive::new(reader).map_err(|e| format!("failed to read zip: {}", e))?;
+pub(crate) async fn set_archive_name_encoding(
+    path: String,
+    encoding: Option<NameEncoding>,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    {
+        let mut archive_options = state.archive_options.write().await;
+        match encoding {
+            Some(encoding) => archive_options
+                .name_encodings
+                .insert(path.clone(), encoding),
+            None => archive_options.name_encodings.remove(&path),
+        };
+    }
+    // 古い文字コードで解析したハンドルを使わないよう破棄する
+    state.archive_cache.invalidate(root_archive_path(&path));
+    refresh_viewer_tabs_for_archive(&path, &state, &app).await
+}
 
-    let mut inner = zip
-        .by_name(&filename)
-        .map_err(|e| format!("file not found in zip: {}", e))?;
-    let mut buf = Vec::with_capacity(inner.size() as usize);
-    inner
-        .read_to_end(&mut buf)
-        .map_err(|e| format!("failed to read file: {}", e))?;
-    Ok(general_purpose::STANDARD_NO_PAD.encode(&buf))
+/// 画像・動画として扱う拡張子の設定（ユーザーが追加した拡張子）を取得する
+#[tauri::command]
+pub(crate) fn get_format_settings() -> FormatSettings {
+    format_registry::format_settings()
+}
+
+/// 画像・動画として扱う拡張子を設定する
+/// 設定は以降に構築するファイルツリーから反映し、終了時に保存する
+#[tauri::command]
+pub(crate) fn set_format_settings(settings: FormatSettings) {
+    format_registry::set_format_settings(settings);
+}
+
+/// 埋め込みの ICC プロファイルの扱いを取得する
+#[tauri::command]
+pub(crate) fn get_color_management() -> ColorManagement {
+    color_utils::color_management()
+}
+
+/// 埋め込みの ICC プロファイルの扱いを設定する
+/// 古い設定で変換した表示用の画像とタイルは破棄する（設定は終了時に保存する）
+#[tauri::command]
+pub(crate) fn set_color_management(mode: ColorManagement, state: State<'_, AppState>) {
+    if color_utils::color_management() == mode {
+        return;
+    }
+    color_utils::set_color_management(mode);
+    state.media_cache.clear_converted();
+    state.tile_cache.clear();
+}
+
+/// 指定したアーカイブ（または内側のアーカイブ）を開いているタブのファイルツリーを再構築する
+async fn refresh_viewer_tabs_for_archive(
+    path: &str,
+    state: &State<'_, AppState>,
+    app: &AppHandle,
+) -> Result<(), String> {
+    let root_path = normalize_path(root_archive_path(path));
+    let targets: Vec<(String, String)> = {
+        let viewers = state.viewers.lock().await;
+        viewers
+            .iter()
+            .flat_map(|viewer| {
+                viewer
+                    .tabs
+                    .iter()
+                    .filter(|tab| normalize_path(&tab.path) == root_path)
+                    .map(|tab| (tab.key.clone(), viewer.label.clone()))
+            })
+            .collect()
+    };
+    for (tab_key, label) in targets {
+        refresh_viewer_tab_tree(tab_key, label, state.clone(), app.clone()).await?;
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -306,6 +500,29 @@ pub(crate) async fn request_restore_viewer_tab_state<'a>(
     Ok(())
 }
 
+/// タブで表示中のファイルの前後の画像を返す
+/// フロントエンドはページ送りの前にこれらの画像をデコードしておく
+#[tauri::command]
+pub(crate) async fn get_neighbor_images(
+    tab_key: String,
+    label: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<File>, String> {
+    let viewers = state.viewers.lock().await;
+    let viewer_state = viewers
+        .iter()
+        .find(|w| w.label == label)
+        .ok_or_else(|| "viewer not found".to_string())?;
+    let tab_state = viewer_state
+        .tabs
+        .iter()
+        .find(|t| t.key == tab_key)
+        .ok_or_else(|| "tab not found".to_string())?;
+    Ok(tab_state.viewing.as_ref().map_or_else(Vec::new, |viewing| {
+        neighbor_images(viewing, &tab_state.tree, viewer_state.navigation_mode)
+    }))
+}
+
 #[tauri::command]
 pub(crate) async fn change_viewing(
     tab_key: String,
@@ -326,6 +543,9 @@ pub(crate) async fn change_viewing(
         .ok_or_else(|| "tab not found".to_string())?;
     let tree = &viewer_state.tabs[index].tree;
     let viewing = find_key_in_tree(tree, &key);
+    if let Some(viewing) = &viewing {
+        prefetch_neighbors(viewing, tree, viewer_state.navigation_mode, &state);
+    }
     viewer_state.tabs[index].viewing = viewing;
     app.emit_to(
         &label,
@@ -336,17 +556,54 @@ pub(crate) async fn change_viewing(
     Ok(())
 }
 
+/// タブのツリーの未展開の内側のアーカイブの中身を読み込む
+/// フロントエンドでツリーの内側のアーカイブを開いたときに呼び出される
+#[tauri::command]
+pub(crate) async fn expand_viewer_directory(
+    tab_key: String,
+    path: String,
+    label: String,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    let tab_state = expand_viewer_tab_directory(&label, &tab_key, &path, &state).await?;
+    app.emit_to(&label, "viewer-tab-state-changed", tab_state)
+        .map_err(|_| "failed to emit viewer state".to_string())?;
+    Ok(())
+}
+
+/// ページ送りでのファイルのたどり方を設定する
+#[tauri::command]
+pub(crate) async fn set_navigation_mode(
+    label: String,
+    mode: NavigationMode,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    let mut viewers = state.viewers.lock().await;
+    let viewer_state = (*viewers)
+        .iter_mut()
+        .find(|w| w.label == label)
+        .ok_or_else(|| "viewer not found".to_string())?;
+    viewer_state.navigation_mode = mode;
+    app.emit_to(&label, "viewer-state-changed", viewer_state.clone())
+        .map_err(|_| "failed to emit viewer state".to_string())?;
+    Ok(())
+}
+
 #[tauri::command]
 pub(crate) async fn move_forward(
     label: String,
     state: State<'_, AppState>,
     app: AppHandle,
 ) -> Result<(), String> {
+    expand_lazy_directories_on_step(&label, true, &state).await?;
     let mut viewers = state.viewers.lock().await;
     let viewer_state = (*viewers)
         .iter_mut()
         .find(|w| w.label == label)
         .ok_or_else(|| "viewer not found".to_string())?;
+    let navigation_mode = viewer_state.navigation_mode;
     let tab_state = viewer_state
         .tabs
         .iter_mut()
@@ -354,12 +611,13 @@ pub(crate) async fn move_forward(
         .ok_or_else(|| "tab not found".to_string())?;
     let old_viewing = tab_state.viewing.clone();
     let viewing = if let Some(File { key, .. }) = old_viewing {
-        get_next_in_tree(&key, &tab_state.tree)
+        get_next_file(&key, &tab_state.tree, navigation_mode)
     } else {
         None
     };
-    if viewing.is_some() {
-        tab_state.viewing = viewing;
+    if let Some(viewing) = viewing {
+        prefetch_neighbors(&viewing, &tab_state.tree, navigation_mode, &state);
+        tab_state.viewing = Some(viewing);
         app.emit_to(&label, "viewer-tab-state-changed", tab_state.clone())
             .map_err(|_| "failed to emit viewer state".to_string())?;
     }
@@ -372,11 +630,13 @@ pub(crate) async fn move_backward(
     state: State<'_, AppState>,
     app: AppHandle,
 ) -> Result<(), String> {
+    expand_lazy_directories_on_step(&label, false, &state).await?;
     let mut viewers = state.viewers.lock().await;
     let viewer_state = (*viewers)
         .iter_mut()
         .find(|w| w.label == label)
         .ok_or_else(|| "viewer not found".to_string())?;
+    let navigation_mode = viewer_state.navigation_mode;
     let tab_state = viewer_state
         .tabs
         .iter_mut()
@@ -384,12 +644,13 @@ pub(crate) async fn move_backward(
         .ok_or_else(|| "tab not found".to_string())?;
     let old_viewing = tab_state.viewing.clone();
     let viewing = if let Some(File { key, .. }) = old_viewing {
-        get_prev_in_tree(&key, &tab_state.tree)
+        get_prev_file(&key, &tab_state.tree, navigation_mode)
     } else {
         None
     };
-    if viewing.is_some() {
-        tab_state.viewing = viewing;
+    if let Some(viewing) = viewing {
+        prefetch_neighbors(&viewing, &tab_state.tree, navigation_mode, &state);
+        tab_state.viewing = Some(viewing);
         app.emit_to(&label, "viewer-tab-state-changed", tab_state.clone())
             .map_err(|_| "failed to emit viewer state".to_string())?;
     }
@@ -475,6 +736,57 @@ pub(crate) async fn close_viewer_tabs_by_directory(
     Ok(())
 }
 
+/// アクティブなタブを親フォルダ内の次のフォルダ（またはアーカイブ）に移す
+/// 並び順は Explorer の並び順に合わせる。次のフォルダがなければ何もしない
+#[tauri::command]
+pub(crate) async fn move_to_next_folder(
+    label: String,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    move_to_sibling_folder(label, true, state, app).await
+}
+
+/// アクティブなタブを親フォルダ内の前のフォルダ（またはアーカイブ）に移す
+/// 並び順は Explorer の並び順に合わせる。前のフォルダがなければ何もしない
+#[tauri::command]
+pub(crate) async fn move_to_prev_folder(
+    label: String,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    move_to_sibling_folder(label, false, state, app).await
+}
+
+/// アクティブなタブを隣のフォルダに移す
+/// 移動先のフォルダの閲覧は、表示するファイルが変わったときにフロントエンドが記録する
+async fn move_to_sibling_folder(
+    label: String,
+    forward: bool,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    let tab_key = {
+        let viewers = state.viewers.lock().await;
+        let viewer_state = (*viewers)
+            .iter()
+            .find(|w| w.label == label)
+            .ok_or_else(|| "viewer not found".to_string())?;
+        viewer_state
+            .active
+            .as_ref()
+            .map(|active| active.key.clone())
+            .ok_or_else(|| "tab not found".to_string())?
+    };
+    let Some(viewer_state) = move_viewer_tab_to_sibling(&label, &tab_key, forward, &state).await?
+    else {
+        return Ok(());
+    };
+    app.emit_to(&label, "viewer-state-changed", &viewer_state)
+        .map_err(|_| "failed to emit viewer state".to_string())?;
+    notify_active_directory_to_explorers(&viewer_state, &state, &app).await
+}
+
 /// フォルダの閲覧を記録する (Phase 2: リコメンド基盤)
 /// Viewer でファイルを開いた際に呼び出し、閲覧履歴とサムネイルを DB に保存
 #[tauri::command]
@@ -486,19 +798,32 @@ pub(crate) async fn record_folder_view(
     use crate::utils::thumbnail_utils::generate_thumbnail_data;
 
     let db = state.db.clone();
+    let archive_options = state.archive_options.read().await.clone();
 
     // バックグラウンドでサムネイル生成と DB 保存を行う
     tokio::task::spawn_blocking(move || {
+        // アーカイブの閲覧時は、アーカイブの表紙画像をサムネイルにする
+        let thumbnail_image_path = thumbnail_image_path
+            .map(|p| {
+                if is_compressed_file(&p) {
+                    find_thumbnail_image(std::path::Path::new(&p), &archive_options)
+                } else {
+                    p
+                }
+            })
+            .filter(|p| !p.is_empty());
+
         // サムネイル画像パスが指定されている場合、サムネイルを生成
         let (thumbnail_blob, thumbnail_hash) = if let Some(ref img_path) = thumbnail_image_path {
-            // 既存のハッシュと比較して変更がある場合のみ再生成
+            // 元画像のハッシュと比較して変更がある場合か、古い作り方のサムネイルの場合のみ再生成
             let existing_hash = db.get_thumbnail_hash(&folder_path).ok().flatten();
             let current_hash = crate::utils::thumbnail_utils::calculate_image_hash(img_path).ok();
+            let is_current = db.is_thumbnail_current(&folder_path).unwrap_or(false);
 
-            if existing_hash.as_ref() != current_hash.as_ref() {
-                // サムネイルを生成
+            if existing_hash.as_ref() != current_hash.as_ref() || !is_current {
+                // サムネイルを生成（変更検知のため元画像のハッシュを保存する）
                 match generate_thumbnail_data(img_path) {
-                    Ok(data) => (Some(data.blob), Some(data.hash)),
+                    Ok(data) => (Some(data.blob), current_hash),
                     Err(e) => {
                         eprintln!("Failed to generate thumbnail: {}", e);
                         (None, None)
@@ -2,7 +2,7 @@
 
 use tauri_plugin_dialog::DialogExt;
 
-use crate::utils::file_utils::get_any_extensions;
+use crate::utils::format_registry::{extensions_of, MediaKind};
 
 // types.rs からの再エクスポート（後方互換性）
 pub use super::types::{ActiveTab, ActiveViewer, AppState};
@@ -14,15 +14,25 @@ pub use super::types::{ActiveTab, ActiveViewer, AppState};
 pub(crate) async fn open_file_pick_dialog(app: &tauri::AppHandle) -> Result<String, String> {
     use tokio::sync::oneshot;
 
-    let extensions = get_any_extensions();
+    let image_extensions = dialog_extensions(MediaKind::Image);
+    let video_extensions = dialog_extensions(MediaKind::Video);
+    let archive_extensions = dialog_extensions(MediaKind::Archive);
+    let any_extensions: Vec<String> = [&image_extensions, &video_extensions, &archive_extensions]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    fn as_strs(extensions: &[String]) -> Vec<&str> {
+        extensions.iter().map(String::as_str).collect()
+    }
     let (tx, rx) = oneshot::channel();
 
     app.dialog()
         .file()
-        .add_filter(
-            "File",
-            &extensions.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
-        )
+        .add_filter("File", &as_strs(&any_extensions))
+        .add_filter("Image", &as_strs(&image_extensions))
+        .add_filter("Video", &as_strs(&video_extensions))
+        .add_filter("Archive", &as_strs(&archive_extensions))
         .pick_file(move |file_path| {
             let _ = tx.send(file_path);
         });
@@ -33,3 +43,12 @@ pub(crate) async fn open_file_pick_dialog(app: &tauri::AppHandle) -> Result<Stri
         Err(_) => Err("dialog cancelled".to_string()),
     }
 }
+
+/// ファイル選択ダイアログのフィルタに使う拡張子
+/// 大文字小文字を区別する環境 (GTK) でも選べるよう、大文字の拡張子も加える
+fn dialog_extensions(kind: MediaKind) -> Vec<String> {
+    extensions_of(kind)
+        .into_iter()
+        .flat_map(|ext| [ext.clone(), ext.to_uppercase()])
+        .collect()
+}
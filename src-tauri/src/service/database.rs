@@ -8,6 +8,8 @@ use std::path::Path;
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::utils::thumbnail_utils::THUMBNAIL_VERSION;
+
 /// データベース接続のラッパー
 pub struct Database {
     conn: Mutex<Connection>,
@@ -86,10 +88,28 @@ impl Database {
             )?;
         }
 
+        // サムネイルの作り方のバージョン（既存のサムネイルは 0 になり、次の閲覧時に作り直す）
+        let has_thumbnail_version: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('folder_records') WHERE name='thumbnail_version'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0) > 0;
+
+        if !has_thumbnail_version {
+            conn.execute_batch(
+                r#"
+                ALTER TABLE folder_records ADD COLUMN thumbnail_version INTEGER DEFAULT 0;
+                "#,
+            )?;
+        }
+
         Ok(())
     }
 
     /// フォルダの閲覧を記録する
+    /// サムネイルを渡した場合は、現在の作り方のバージョンも記録する
     pub fn record_folder_view(
         &self,
         folder_path: &str,
@@ -104,15 +124,16 @@ impl Database {
 
         conn.execute(
             r#"
-            INSERT INTO folder_records (path, thumbnail_blob, thumbnail_hash, last_viewed_at, view_count, created_at)
-            VALUES (?1, ?2, ?3, ?4, 1, ?4)
+            INSERT INTO folder_records (path, thumbnail_blob, thumbnail_hash, thumbnail_version, last_viewed_at, view_count, created_at)
+            VALUES (?1, ?2, ?3, CASE WHEN ?2 IS NULL THEN 0 ELSE ?5 END, ?4, 1, ?4)
             ON CONFLICT(path) DO UPDATE SET
                 thumbnail_blob = COALESCE(?2, thumbnail_blob),
                 thumbnail_hash = COALESCE(?3, thumbnail_hash),
+                thumbnail_version = CASE WHEN ?2 IS NULL THEN thumbnail_version ELSE ?5 END,
                 last_viewed_at = ?4,
                 view_count = view_count + 1
             "#,
-            rusqlite::params![folder_path, thumbnail_blob, thumbnail_hash, now],
+            rusqlite::params![folder_path, thumbnail_blob, thumbnail_hash, now, THUMBNAIL_VERSION],
         )?;
 
         Ok(())
@@ -133,13 +154,14 @@ impl Database {
 
         conn.execute(
             r#"
-            INSERT INTO folder_records (path, thumbnail_blob, thumbnail_hash, last_viewed_at, view_count, created_at)
-            VALUES (?1, ?2, ?3, NULL, 0, ?4)
+            INSERT INTO folder_records (path, thumbnail_blob, thumbnail_hash, thumbnail_version, last_viewed_at, view_count, created_at)
+            VALUES (?1, ?2, ?3, ?5, NULL, 0, ?4)
             ON CONFLICT(path) DO UPDATE SET
                 thumbnail_blob = ?2,
-                thumbnail_hash = ?3
+                thumbnail_hash = ?3,
+                thumbnail_version = ?5
             "#,
-            rusqlite::params![folder_path, thumbnail_blob, thumbnail_hash, now],
+            rusqlite::params![folder_path, thumbnail_blob, thumbnail_hash, now, THUMBNAIL_VERSION],
         )?;
 
         Ok(())
@@ -160,6 +182,22 @@ impl Database {
         Ok(result)
     }
 
+    /// 指定パスのサムネイルが現在の作り方で作られているか（レコードやサムネイルがなければ false）
+    pub fn is_thumbnail_current(&self, folder_path: &str) -> Result<bool> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let version: Option<i32> = conn
+            .query_row(
+                "SELECT thumbnail_version FROM folder_records WHERE path = ?1 AND thumbnail_blob IS NOT NULL",
+                [folder_path],
+                |row| row.get(0),
+            )
+            .ok()
+            .flatten();
+
+        Ok(version.is_some_and(|v| v >= THUMBNAIL_VERSION))
+    }
+
     /// 直近閲覧した N 件のフォルダパスを取得する（ディレクトリ問わず）
     pub fn get_recent_viewed_folders(&self, limit: usize) -> Result<Vec<String>> {
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
@@ -334,9 +372,11 @@ impl Database {
     }
 
     /// 複数パスの folder_modified_at を一括取得（差分更新チェック用）
+    /// 埋め込みが `current_version` より古いレコードは作り直すため含めない
     pub fn get_folder_modified_at_batch(
         &self,
         paths: &[String],
+        current_version: i32,
     ) -> Result<std::collections::HashMap<String, i64>> {
         if paths.is_empty() {
             return Ok(std::collections::HashMap::new());
@@ -344,16 +384,20 @@ impl Database {
 
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
 
-        let placeholders: Vec<String> = (1..=paths.len()).map(|i| format!("?{}", i)).collect();
+        let placeholders: Vec<String> = (2..=paths.len() + 1).map(|i| format!("?{}", i)).collect();
         let query = format!(
-            "SELECT path, folder_modified_at FROM folder_records WHERE path IN ({}) AND folder_modified_at IS NOT NULL",
+            "SELECT path, folder_modified_at FROM folder_records WHERE path IN ({}) AND folder_modified_at IS NOT NULL AND embedding_version >= ?1",
             placeholders.join(", ")
         );
 
         let mut stmt = conn.prepare(&query)?;
 
+        let params: Vec<&dyn rusqlite::ToSql> =
+            std::iter::once(&current_version as &dyn rusqlite::ToSql)
+                .chain(paths.iter().map(|p| p as &dyn rusqlite::ToSql))
+                .collect();
         let records: std::collections::HashMap<String, i64> = stmt
-            .query_map(rusqlite::params_from_iter(paths.iter()), |row| {
+            .query_map(params.as_slice(), |row| {
                 Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
             })?
             .filter_map(|r| r.ok())
@@ -10,11 +10,14 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tract_onnx::prelude::*;
 
+use crate::utils::image_utils::decode_image;
+
 /// CLIP 埋め込みの次元数
 pub const EMBEDDING_DIM: usize = 512;
 
-/// 現在の埋め込みモデルバージョン（モデル更新時にインクリメント）
-pub const EMBEDDING_VERSION: i32 = 1;
+/// 現在の埋め込みモデルバージョン（モデル更新時や、入力画像の作り方を変えたときにインクリメント）
+/// 2: EXIF の向きの適用と ICC プロファイルによる sRGB への変換後の画像から作り直す
+pub const EMBEDDING_VERSION: i32 = 2;
 
 /// 埋め込みベクトルを L2 正規化
 fn normalize_embedding(embedding: &[f32]) -> Vec<f32> {
@@ -124,10 +127,10 @@ impl EmbeddingService {
     }
 
     /// 画像の埋め込みベクトルを生成
-    /// 入力: 画像データ (JPEG/PNG など、image crate がデコードできる形式)
+    /// 入力: 画像データ (JPEG/PNG/HEIC/JPEG XL など、image_utils でデコードできる形式)
     pub async fn generate_image_embedding(&self, image_data: &[u8]) -> Result<Vec<f32>> {
         // 画像をデコードしてリサイズ
-        let img = image::load_from_memory(image_data).context("Failed to decode image")?;
+        let img = decode_image(image_data).context("Failed to decode image")?;
         let img = img.resize_exact(224, 224, image::imageops::FilterType::Lanczos3);
         let rgb = img.to_rgb8();
 
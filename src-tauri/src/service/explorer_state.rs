@@ -7,7 +7,10 @@ use sysinfo::Disks;
 use tauri::State;
 use tokio::sync::RwLock;
 
-use crate::utils::file_utils::find_first_image_in_folder;
+use crate::utils::archive::{
+    cached_archive_metadata, get_archive_metadata, ArchiveMetadata, ArchiveOptions,
+};
+use crate::utils::file_utils::{find_thumbnail_image, is_compressed_file};
 
 use crate::service::database::Database;
 use crate::service::embedding_service::{
@@ -28,6 +31,8 @@ pub struct CachedDirEntry {
     pub filename: String,
     pub modified_at: Option<u64>,
     pub created_at: Option<u64>,
+    pub is_archive: bool,
+    pub metadata: Option<ArchiveMetadata>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -37,6 +42,12 @@ pub struct Thumbnail {
     pub thumbpath: String,
     pub modified_at: Option<u64>,
     pub created_at: Option<u64>,
+    /// フォルダではなくアーカイブファイル (ZIP / CBZ など) のエントリか
+    #[serde(default)]
+    pub is_archive: bool,
+    /// アーカイブのメタデータ (ComicInfo.xml)
+    #[serde(default)]
+    pub metadata: Option<ArchiveMetadata>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -434,7 +445,9 @@ fn calculate_recommendation_scores(db: &Database, folder_paths: &[String]) -> Ha
 }
 
 /// ディレクトリをスキャン・ソートし、CachedDirEntry 一覧を返す（同期関数、spawn_blocking から呼ぶ）
-fn scan_and_sort_dirs_sync(
+/// アーカイブファイルもフォルダと同様に一覧へ含め、解析済みの ComicInfo.xml があればそのタイトルでも検索する
+/// 名前順はファイル名で並べる（解析済みかどうかで並び順が変わらないよう、ComicInfo.xml のタイトルは使わない）
+pub(crate) fn scan_and_sort_dirs_sync(
     filepath: &str,
     sort: &SortConfig,
     search_query: Option<&str>,
@@ -445,18 +458,43 @@ fn scan_and_sort_dirs_sync(
     let dirs = std::fs::read_dir(filepath).map_err(|_| "failed to open path")?;
     let mut entries: Vec<_> = dirs
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_dir())
+        .filter(|e| {
+            let path = e.path();
+            path.is_dir() || is_compressed_file(path.to_str().unwrap_or_default())
+        })
         .collect();
 
+    // アーカイブのメタデータは解析済みのものだけを使う
+    // 未解析のアーカイブは一覧を返した後、表示するページの分だけサムネイルと一緒に解析する
+    let archive_metadata: HashMap<std::path::PathBuf, ArchiveMetadata> = entries
+        .iter()
+        .map(|e| e.path())
+        .filter(|p| !p.is_dir())
+        .filter_map(|p| {
+            let metadata = cached_archive_metadata(p.to_str()?)??;
+            Some((p, metadata))
+        })
+        .collect();
+    // ComicInfo.xml のタイトル（解析済みの場合のみ）
+    let display_title = |e: &std::fs::DirEntry| {
+        archive_metadata
+            .get(&e.path())
+            .and_then(|m| m.display_title.as_ref())
+            .map(|title| title.to_lowercase())
+    };
+
     // 検索フィルタリング
     if let Some(query) = search_query {
         if !query.is_empty() {
             let query_lower = query.to_lowercase();
             entries.retain(|e| {
-                e.file_name()
+                let file_name_matched = e
+                    .file_name()
                     .to_str()
                     .map(|name| name.to_lowercase().contains(&query_lower))
-                    .unwrap_or(false)
+                    .unwrap_or(false);
+                file_name_matched
+                    || display_title(e).is_some_and(|title| title.contains(&query_lower))
             });
         }
     }
@@ -529,17 +567,22 @@ fn scan_and_sort_dirs_sync(
             filename: e.file_name().to_str().unwrap_or_default().to_string(),
             modified_at: modified,
             created_at: created,
+            is_archive: !e.path().is_dir(),
+            metadata: archive_metadata.get(&e.path()).cloned(),
         })
         .collect())
 }
 
 /// ディレクトリスキャン、ソート、ページネーション、サムネイル抽出を統合した最適化版
 /// dir_list_cache を利用して同一条件の再スキャンを省略する
+/// 未解析のアーカイブのメタデータは、表示するページの分だけサムネイルと並列に解析する
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn explore_path_with_count(
     filepath: &str,
     page: usize,
     cache: Arc<RwLock<HashMap<String, String>>>,
     dir_list_cache: Arc<RwLock<HashMap<String, Vec<CachedDirEntry>>>>,
+    archive_options: Arc<RwLock<ArchiveOptions>>,
     sort: &SortConfig,
     search_query: Option<&str>,
     db: Option<Arc<Database>>,
@@ -597,8 +640,9 @@ pub(crate) async fn explore_path_with_count(
     }
 
     let page_entries = &all_entries[start..end];
+    let archive_options = archive_options.read().await.clone();
 
-    // 4. サムネイル抽出 (並列処理)
+    // 4. サムネイル・メタデータ抽出 (並列処理)
     let tasks: Vec<_> = page_entries
         .iter()
         .map(|entry| {
@@ -608,8 +652,25 @@ pub(crate) async fn explore_path_with_count(
             let cache = cache.clone();
             let modified = entry.modified_at;
             let created = entry.created_at;
+            let is_archive = entry.is_archive;
+            let metadata = entry.metadata.clone();
+            let archive_options = archive_options.clone();
+            let thumbnail_archive_options = archive_options.clone();
 
             tokio::spawn(async move {
+                // 未解析のアーカイブを解析する（解析結果はディスクにキャッシュされる）
+                let metadata = match (is_archive, metadata) {
+                    (true, None) => {
+                        let path = path_str.clone();
+                        tokio::task::spawn_blocking(move || {
+                            get_archive_metadata(&path, &archive_options).ok().flatten()
+                        })
+                        .await
+                        .unwrap_or_default()
+                    }
+                    (_, metadata) => metadata,
+                };
+
                 // キャッシュチェック
                 {
                     let cache_read = cache.read().await;
@@ -620,15 +681,18 @@ pub(crate) async fn explore_path_with_count(
                             thumbpath: thumb.clone(),
                             modified_at: modified,
                             created_at: created,
+                            is_archive,
+                            metadata,
                         };
                     }
                 }
 
                 // キャッシュミス: ブロッキングI/Oで検索
-                let thumb =
-                    tokio::task::spawn_blocking(move || find_first_image_in_folder(&path_buf))
-                        .await
-                        .unwrap_or_default();
+                let thumb = tokio::task::spawn_blocking(move || {
+                    find_thumbnail_image(&path_buf, &thumbnail_archive_options)
+                })
+                .await
+                .unwrap_or_default();
 
                 // キャッシュに保存
                 {
@@ -642,6 +706,8 @@ pub(crate) async fn explore_path_with_count(
                     thumbpath: thumb,
                     modified_at: modified,
                     created_at: created,
+                    is_archive,
+                    metadata,
                 }
             })
         })
@@ -671,6 +737,8 @@ pub(crate) fn get_devices() -> Result<Vec<Thumbnail>, String> {
                 thumbpath: "".to_string(),
                 modified_at: None,
                 created_at: None,
+                is_archive: false,
+                metadata: None,
             }
         })
         .collect())
@@ -10,6 +10,10 @@ use super::database::Database;
 use super::embedding_service::EmbeddingService;
 use super::explorer_state::{CachedDirEntry, ExplorerState};
 use super::viewer_state::ViewerState;
+use crate::utils::animation_utils::AnimationCache;
+use crate::utils::archive::{ArchiveCache, ArchiveOptions};
+use crate::utils::media_cache::MediaCache;
+use crate::utils::tile_utils::TileCache;
 
 // ========================================
 // 共通型定義
@@ -41,6 +45,16 @@ pub struct AppState {
     /// ディレクトリ一覧キャッシュ (cache_key -> ソート済みエントリ一覧)
     /// cache_key = "{dir_path}|{sort_field:sort_order}|{search_query}"
     pub dir_list_cache: Arc<RwLock<HashMap<String, Vec<CachedDirEntry>>>>,
+    /// アーカイブを開く際の設定（ファイル名の文字コード指定など、セッション中のみ保持）
+    pub archive_options: Arc<RwLock<ArchiveOptions>>,
+    /// 開いたアーカイブのハンドルキャッシュ (LRU、キーはパス + 更新日時)
+    pub archive_cache: Arc<ArchiveCache>,
+    /// 表示中のファイルの前後を先読みしたバイト列のキャッシュ (LRU、合計サイズで上限を設ける)
+    pub media_cache: Arc<MediaCache>,
+    /// 巨大な画像の拡大表示用タイルのキャッシュ（アプリのデータディレクトリに保存）
+    pub tile_cache: Arc<TileCache>,
+    /// コマ送り用に全フレームへ展開したアニメーション画像のキャッシュ（直近の 1 枚のみ）
+    pub animation_cache: Arc<AnimationCache>,
     /// SQLite データベース (Phase 2: リコメンド基盤)
     pub db: Arc<Database>,
     /// CLIP 埋め込みサービス (Phase 4: ML リコメンド)
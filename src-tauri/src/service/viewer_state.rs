@@ -1,13 +1,21 @@
 //! Viewer関連の状態管理
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tauri::State;
 
+use super::explorer_state::scan_and_sort_dirs_sync;
+use super::explorer_types::SortConfig;
 use super::types::{ActiveTab, AppState};
+use crate::utils::archive::{
+    join_nested_archive_path, read_comic_info, ArchiveCache, ArchiveFormat, ArchiveMetadata,
+    ArchiveOptions, ArchiveReader, NESTED_ARCHIVE_SEPARATOR,
+};
 use crate::utils::file_utils::{
     get_filename_without_extension, get_parent_dir, get_parent_dir_name, is_compressed_file,
-    is_executable_file, is_image_file, is_video_file,
+    is_executable_file, is_image_file, normalize_path,
 };
+use crate::utils::format_registry::{detect_media_kind, media_kind_from_path, MediaKind};
 
 // ========================================
 // 型定義
@@ -18,14 +26,30 @@ pub struct Directory {
     pub path: String,
     pub name: String,
     pub children: Vec<FileTree>,
+    /// まだ中身を読み込んでいない内側のアーカイブ（`children` は空）
+    /// 開かれたときに中身を読み込む
+    #[serde(default)]
+    pub lazy: bool,
+}
+
+/// ツリー上のファイルの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileType {
+    Image,
+    Video,
+    /// アーカイブ内のエントリ（`path` はアーカイブ、`name` はエントリのパス）
+    Zip,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct File {
     pub key: String,
-    pub file_type: String,
+    pub file_type: FileType,
     pub path: String,
     pub name: String,
+    /// 動画か（アーカイブ内のエントリは、設定で追加した拡張子を含めて `format_registry` で判定する）
+    #[serde(default)]
+    pub is_video: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +65,22 @@ pub struct ViewerTabState {
     pub path: String,
     pub viewing: Option<File>,
     pub tree: Vec<FileTree>,
+    /// アーカイブのメタデータ (ComicInfo.xml)
+    #[serde(default)]
+    pub metadata: Option<ArchiveMetadata>,
+}
+
+/// ページ送りでのファイルのたどり方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NavigationMode {
+    /// 表示中のファイルと同じフォルダ内だけを移動し、末尾の次は先頭に戻る
+    #[default]
+    Folder,
+    /// ツリー全体を深さ優先でたどり、フォルダの末尾の次は次のフォルダに移る
+    /// ツリーの末尾の次は先頭に戻る
+    TreeWrap,
+    /// ツリー全体を深さ優先でたどり、ツリーの先頭・末尾で止まる
+    TreeStop,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +89,9 @@ pub struct ViewerState {
     pub count: i32,
     pub active: Option<ActiveTab>,
     pub tabs: Vec<ViewerTabState>,
+    /// ページ送りでのファイルのたどり方
+    #[serde(default)]
+    pub navigation_mode: NavigationMode,
 }
 
 // ========================================
@@ -63,6 +106,7 @@ pub(crate) async fn add_viewer_state<'a>(state: &State<'a, AppState>) -> Result<
         count: 0,
         active: None,
         tabs: vec![],
+        navigation_mode: NavigationMode::default(),
     });
     *state.count.lock().await += 1;
     Ok(label)
@@ -98,42 +142,62 @@ pub(crate) async fn add_viewer_tab_state<'a>(
         get_parent_dir(path)
     };
 
-    // ロックを短時間だけ保持してタブキーを生成する
-    let key = {
+    // ロックを短時間だけ保持してタブキーを生成し、先読みに使うページ送りの設定を取得する
+    let (key, navigation_mode) = {
         let mut viewers = state.viewers.lock().await;
         let viewer_state = (*viewers)
             .iter_mut()
             .find(|w| w.label == *label)
             .ok_or_else(|| "viewer not found".to_string())?;
         viewer_state.count += 1;
-        format!("tab-{}", viewer_state.count)
+        (
+            format!("tab-{}", viewer_state.count),
+            viewer_state.navigation_mode,
+        )
     };
 
     // ファイルツリー構築をロック外のブロッキングスレッドで実行（同期 I/O がロックを長期保持しないよう分離）
     let new_path_clone = new_path.clone();
-    let tree = tokio::task::spawn_blocking(move || {
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let (tree, metadata) = tokio::task::spawn_blocking(move || {
         if is_compressed {
-            get_compressed_file_tree(&new_path_clone)
+            let tree = get_compressed_file_tree(&new_path_clone, &archive_options, &archive_cache);
+            let metadata = archive_cache
+                .with_archive(&new_path_clone, &archive_options, read_comic_info)
+                .unwrap_or_default();
+            (tree, metadata)
         } else {
             let mut key_count = 0;
-            get_file_tree(&new_path_clone, &mut key_count)
+            (get_file_tree(&new_path_clone, &mut key_count), None)
         }
     })
     .await
     .map_err(|e| format!("Failed to build file tree: {}", e))?;
 
+    // ComicInfo.xml があればファイル名よりもそのタイトルを優先する
+    let title = metadata
+        .as_ref()
+        .and_then(|m| m.display_title.clone())
+        .unwrap_or(title);
+
     let viewing = if is_compressed {
         find_first_file(&tree)
     } else {
         find_path_in_tree(&tree, path)
     };
 
+    if let Some(viewing) = &viewing {
+        prefetch_neighbors(viewing, &tree, navigation_mode, state);
+    }
+
     let tab = ViewerTabState {
         title,
         key: key.clone(),
         path: new_path,
         viewing,
         tree,
+        metadata,
     };
 
     // ロックを再取得してタブを追加する
@@ -147,6 +211,205 @@ pub(crate) async fn add_viewer_tab_state<'a>(
     Ok(viewer_state.clone())
 }
 
+/// タブが表示しているフォルダの親フォルダを開いている Explorer のタブの並び順
+/// 開いている Explorer がなければ Explorer の既定の並び順を返す
+async fn explorer_sort_for(dir: &str, state: &State<'_, AppState>) -> SortConfig {
+    let dir = normalize_path(dir);
+    let explorers = state.explorers.lock().await;
+    explorers
+        .iter()
+        .flat_map(|explorer| explorer.tabs.iter())
+        .find(|tab| tab.path.as_deref().map(normalize_path).as_ref() == Some(&dir))
+        .map(|tab| tab.sort.clone())
+        .unwrap_or_default()
+}
+
+/// タブのフォルダ（またはアーカイブ）の隣のフォルダ（またはアーカイブ）にタブを移す
+/// 隣は親フォルダを Explorer と同じ並び順で並べたときの次（`forward` が false なら前）とし、
+/// 表示できるファイルを含まないものは飛ばす。移動先がなければ None を返す
+pub(crate) async fn move_viewer_tab_to_sibling(
+    label: &String,
+    tab_key: &String,
+    forward: bool,
+    state: &State<'_, AppState>,
+) -> Result<Option<ViewerState>, String> {
+    let (path, navigation_mode) = {
+        let viewers = state.viewers.lock().await;
+        let viewer_state = (*viewers)
+            .iter()
+            .find(|w| w.label == *label)
+            .ok_or_else(|| "viewer not found".to_string())?;
+        let tab_state = viewer_state
+            .tabs
+            .iter()
+            .find(|t| t.key == *tab_key)
+            .ok_or_else(|| "tab not found".to_string())?;
+        (tab_state.path.clone(), viewer_state.navigation_mode)
+    };
+    let parent = get_parent_dir(&path);
+    let sort = explorer_sort_for(&parent, state).await;
+
+    // フォルダの走査とツリー構築はロック外のブロッキングスレッドで行う
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let db = state.db.clone();
+    let sibling = tokio::task::spawn_blocking(move || {
+        let entries = scan_and_sort_dirs_sync(&parent, &sort, None, Some(&db))?;
+        let current = normalize_path(&path);
+        let Some(index) = entries
+            .iter()
+            .position(|entry| normalize_path(&entry.path) == current)
+        else {
+            return Ok(None);
+        };
+        let candidates: Vec<_> = match forward {
+            true => entries[index + 1..].iter().collect(),
+            false => entries[..index].iter().rev().collect(),
+        };
+        for entry in candidates {
+            let tree = rebuild_file_tree(
+                &entry.path,
+                entry.is_archive,
+                &archive_options,
+                &archive_cache,
+            );
+            if tree.is_empty() {
+                continue;
+            }
+            let metadata = match entry.is_archive {
+                true => archive_cache
+                    .with_archive(&entry.path, &archive_options, read_comic_info)
+                    .unwrap_or_default(),
+                false => None,
+            };
+            return Ok(Some((entry.clone(), tree, metadata)));
+        }
+        Ok::<_, String>(None)
+    })
+    .await
+    .map_err(|e| format!("Failed to build file tree: {}", e))??;
+    let Some((entry, tree, metadata)) = sibling else {
+        return Ok(None);
+    };
+
+    // タブの名前は新しく開いたときと同じく、フォルダ名かアーカイブのファイル名（ComicInfo.xml のタイトル）にする
+    let title = metadata
+        .as_ref()
+        .and_then(|m| m.display_title.clone())
+        .unwrap_or_else(|| match entry.is_archive {
+            true => get_filename_without_extension(&entry.path),
+            false => entry.filename.clone(),
+        });
+    let viewing = find_first_file(&tree);
+    if let Some(viewing) = &viewing {
+        prefetch_neighbors(viewing, &tree, navigation_mode, state);
+    }
+
+    let mut viewers = state.viewers.lock().await;
+    let viewer_state = (*viewers)
+        .iter_mut()
+        .find(|w| w.label == *label)
+        .ok_or_else(|| "viewer not found".to_string())?;
+    let tab_state = viewer_state
+        .tabs
+        .iter_mut()
+        .find(|t| t.key == *tab_key)
+        .ok_or_else(|| "tab not found".to_string())?;
+    tab_state.title = title;
+    tab_state.path = entry.path;
+    tab_state.viewing = viewing;
+    tab_state.tree = tree;
+    tab_state.metadata = metadata;
+    Ok(Some(viewer_state.clone()))
+}
+
+/// タブのツリーの未展開の内側のアーカイブ `path` の中身を読み込む
+/// 読み込み済みの場合は何もせず、現在のタブの状態を返す
+pub(crate) async fn expand_viewer_tab_directory(
+    label: &String,
+    tab_key: &String,
+    path: &str,
+    state: &State<'_, AppState>,
+) -> Result<ViewerTabState, String> {
+    let root = {
+        let viewers = state.viewers.lock().await;
+        let tab_state = (*viewers)
+            .iter()
+            .find(|w| w.label == *label)
+            .ok_or_else(|| "viewer not found".to_string())?
+            .tabs
+            .iter()
+            .find(|t| t.key == *tab_key)
+            .ok_or_else(|| "tab not found".to_string())?;
+        if !has_lazy_directory(&tab_state.tree, path) {
+            return Ok(tab_state.clone());
+        }
+        tab_state.path.clone()
+    };
+
+    // 内側のアーカイブの展開はロック外のブロッキングスレッドで行う
+    let archive_options = state.archive_options.read().await.clone();
+    let archive_cache = state.archive_cache.clone();
+    let lazy_path = path.to_string();
+    let children = tokio::task::spawn_blocking(move || {
+        build_lazy_directory(&root, &lazy_path, &archive_options, &archive_cache)
+    })
+    .await
+    .map_err(|e| format!("Failed to build file tree: {}", e))?;
+
+    let mut viewers = state.viewers.lock().await;
+    let viewer_state = (*viewers)
+        .iter_mut()
+        .find(|w| w.label == *label)
+        .ok_or_else(|| "viewer not found".to_string())?;
+    let tab_state = viewer_state
+        .tabs
+        .iter_mut()
+        .find(|t| t.key == *tab_key)
+        .ok_or_else(|| "tab not found".to_string())?;
+    set_lazy_directory_children(&mut tab_state.tree, path, children);
+    Ok(tab_state.clone())
+}
+
+/// ツリー全体をたどるモードで、アクティブなタブの表示中のファイルから次（`forward` が false なら前）の
+/// ファイルまでの間にある未展開の内側のアーカイブを読み込む
+/// 読み込まずにたどると、内側のアーカイブの中のファイルが飛ばされる
+pub(crate) async fn expand_lazy_directories_on_step(
+    label: &String,
+    forward: bool,
+    state: &State<'_, AppState>,
+) -> Result<(), String> {
+    loop {
+        let (tab_key, path) = {
+            let viewers = state.viewers.lock().await;
+            let viewer_state = (*viewers)
+                .iter()
+                .find(|w| w.label == *label)
+                .ok_or_else(|| "viewer not found".to_string())?;
+            let Some(tab_state) = viewer_state
+                .active
+                .as_ref()
+                .and_then(|active| viewer_state.tabs.iter().find(|t| t.key == active.key))
+            else {
+                return Ok(());
+            };
+            let Some(path) = tab_state.viewing.as_ref().and_then(|viewing| {
+                find_lazy_directory_on_step(
+                    &viewing.key,
+                    &tab_state.tree,
+                    viewer_state.navigation_mode,
+                    forward,
+                )
+            }) else {
+                return Ok(());
+            };
+            (tab_state.key.clone(), path)
+        };
+        // 展開した（または中身がなく取り除いた）内側のアーカイブは未展開でなくなるため、繰り返しは終わる
+        expand_viewer_tab_directory(label, &tab_key, &path, state).await?;
+    }
+}
+
 pub(crate) async fn remove_viewer_tab_state(
     label: &String,
     key: &String,
@@ -180,9 +443,14 @@ pub(crate) async fn remove_viewer_tab_state(
 
 /// ディレクトリのファイルツリーを再構築する
 /// ディレクトリ変更通知を受けた際にフロントエンドから呼び出される
-pub(crate) fn rebuild_file_tree(path: &str, is_compressed: bool) -> Vec<FileTree> {
+pub(crate) fn rebuild_file_tree(
+    path: &str,
+    is_compressed: bool,
+    archive_options: &ArchiveOptions,
+    archive_cache: &ArchiveCache,
+) -> Vec<FileTree> {
     if is_compressed {
-        get_compressed_file_tree(&path.to_string())
+        get_compressed_file_tree(path, archive_options, archive_cache)
     } else {
         let mut key_count = 0;
         get_file_tree(&path.to_string(), &mut key_count)
@@ -195,39 +463,39 @@ fn get_file_tree(path: &String, key_count: &mut i32) -> Vec<FileTree> {
         Err(_) => return vec![],
     };
     let mut files = dirs
-        .map(|f| {
+        .filter_map(|f| {
             let filepath = f.unwrap().path();
             if filepath.is_dir() {
-                FileTree::Directory(Directory {
+                let children = get_file_tree(
+                    &filepath.to_str().unwrap_or_default().to_string(),
+                    key_count,
+                );
+                if children.is_empty() {
+                    return None;
+                }
+                Some(FileTree::Directory(Directory {
                     path: filepath.to_str().unwrap_or_default().to_string(),
                     name: filepath.file_name().unwrap().to_str().unwrap().to_string(),
-                    children: get_file_tree(
-                        &filepath.to_str().unwrap_or_default().to_string(),
-                        key_count,
-                    ),
-                })
+                    children,
+                    lazy: false,
+                }))
             } else {
+                // 拡張子で判定できないファイルは先頭のバイト列で画像・動画かどうかを判定する
+                let file_type = match detect_media_kind(&filepath)? {
+                    MediaKind::Image => FileType::Image,
+                    MediaKind::Video => FileType::Video,
+                    MediaKind::Archive => return None,
+                };
                 *key_count += 1;
-                let key = format!("file-{}", key_count);
-                let filepath_str = filepath.to_str().unwrap_or_default();
-                FileTree::File(File {
-                    key,
-                    file_type: if is_image_file(filepath_str) {
-                        "Image".to_string()
-                    } else if is_video_file(filepath_str) {
-                        "Video".to_string()
-                    } else {
-                        "File".to_string()
-                    },
-                    path: filepath_str.to_string(),
+                Some(FileTree::File(File {
+                    key: format!("file-{}", key_count),
+                    is_video: file_type == FileType::Video,
+                    file_type,
+                    path: filepath.to_str().unwrap_or_default().to_string(),
                     name: filepath.file_name().unwrap().to_str().unwrap().to_string(),
-                })
+                }))
             }
         })
-        .filter(|f| match f {
-            FileTree::Directory(d) => !d.children.is_empty(),
-            FileTree::File(file) => is_executable_file(&file.path),
-        })
         .collect::<Vec<FileTree>>();
     files.sort_by(|a, b| match (a, b) {
         (FileTree::Directory(_), FileTree::File(_)) => std::cmp::Ordering::Less,
@@ -238,33 +506,237 @@ fn get_file_tree(path: &String, key_count: &mut i32) -> Vec<FileTree> {
     files
 }
 
-/// ZIPファイル内のファイルツリーを取得（ストリーミング読み込み）
-fn get_compressed_file_tree(filepath: &String) -> Vec<FileTree> {
+/// アーカイブ内のディレクトリ構造（ツリー構築用の中間表現）
+#[derive(Default)]
+struct ArchiveDirNode {
+    dirs: HashMap<String, ArchiveDirNode>,
+    /// アーカイブ内のフルパス
+    files: Vec<String>,
+    /// 内側のアーカイブのフルパス
+    archives: Vec<String>,
+}
+
+/// 内側のアーカイブを展開する最大の深さ（アーカイブ爆弾対策）
+const MAX_NESTED_ARCHIVE_DEPTH: usize = 3;
+
+/// アーカイブ内のファイルツリーを取得（形式は拡張子から判定）
+/// 内部のフォルダ構成を Directory として再現し、画像・動画以外のエントリは除外する
+/// アーカイブ内のアーカイブは `outer.zip!/vol1.zip` をパスとする未展開の Directory とし、
+/// 開かれたときに `build_lazy_directory` で中身を読み込む
+fn get_compressed_file_tree(
+    filepath: &str,
+    options: &ArchiveOptions,
+    cache: &ArchiveCache,
+) -> Vec<FileTree> {
+    build_archive_tree(filepath, 0, options, cache)
+}
+
+/// アーカイブ（`depth` 段目の内側のアーカイブ）のファイルツリーを作る
+fn build_archive_tree(
+    filepath: &str,
+    depth: usize,
+    options: &ArchiveOptions,
+    cache: &ArchiveCache,
+) -> Vec<FileTree> {
+    let root = cache
+        .with_archive(filepath, options, |archive| {
+            Ok(build_archive_dir_node(archive, depth))
+        })
+        .unwrap_or_default();
     let mut key_count = 0;
-    let file = match std::fs::File::open(filepath) {
-        Ok(f) => f,
-        Err(_) => return vec![],
-    };
-    let reader = std::io::BufReader::new(file);
-    let zip = match zip::ZipArchive::new(reader) {
-        Ok(z) => z,
-        Err(_) => return vec![],
+    archive_node_to_tree(filepath, "", root, &mut key_count)
+}
+
+fn archive_node_to_tree(
+    filepath: &str,
+    dir_path: &str,
+    node: ArchiveDirNode,
+    key_count: &mut i32,
+) -> Vec<FileTree> {
+    let mut dirs: Vec<_> = node.dirs.into_iter().collect();
+    dirs.sort_by(|a, b| natord::compare(&a.0, &b.0));
+    let mut archives = node.archives;
+    archives.sort_by(|a, b| natord::compare(a, b));
+    let mut files = node.files;
+    files.sort_by(|a, b| natord::compare(a, b));
+
+    let mut tree = vec![];
+    for (name, child) in dirs {
+        let path = if dir_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", dir_path, name)
+        };
+        let children = archive_node_to_tree(filepath, &path, child, key_count);
+        if !children.is_empty() {
+            tree.push(FileTree::Directory(Directory {
+                path,
+                name,
+                children,
+                lazy: false,
+            }));
+        }
+    }
+    // 内側のアーカイブはここでは開かず（展開のコストを開くときまで遅らせる）、未展開として並べる
+    for entry_name in archives {
+        let name = entry_name
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        tree.push(FileTree::Directory(Directory {
+            path: join_nested_archive_path(filepath, &entry_name),
+            name,
+            children: vec![],
+            lazy: true,
+        }));
+    }
+    for name in files {
+        *key_count += 1;
+        tree.push(FileTree::File(File {
+            key: format!("file-{}", key_count),
+            file_type: FileType::Zip,
+            path: filepath.to_string(),
+            is_video: media_kind_from_path(&name) == Some(MediaKind::Video),
+            name,
+        }));
+    }
+    tree
+}
+
+/// 未展開の内側のアーカイブ `path` の中身のツリーを作る（`root` はタブで開いているアーカイブのパス）
+/// キーは `set_lazy_directory_children` でツリーに加えるときに振り直す
+pub(crate) fn build_lazy_directory(
+    root: &str,
+    path: &str,
+    options: &ArchiveOptions,
+    cache: &ArchiveCache,
+) -> Vec<FileTree> {
+    let depth = path.matches(NESTED_ARCHIVE_SEPARATOR).count()
+        - root.matches(NESTED_ARCHIVE_SEPARATOR).count();
+    build_archive_tree(path, depth, options, cache)
+}
+
+/// ツリーの未展開の内側のアーカイブ `path` に中身を設定する
+/// 中身が空（表示できるファイルがない・開けない）なら取り除く。未展開の `path` がなければ false を返す
+/// 加えるファイルのキーは、ツリーの既存のキーと重複しないよう振り直す
+pub(crate) fn set_lazy_directory_children(
+    tree: &mut Vec<FileTree>,
+    path: &str,
+    mut children: Vec<FileTree>,
+) -> bool {
+    let Some((parent, position)) = find_lazy_directory(tree, path) else {
+        return false;
     };
-    let mut files: Vec<String> = zip.file_names().map(|s| s.into()).collect();
-    files.sort();
-    files
-        .iter()
-        .map(|f| {
-            key_count += 1;
-            let key = format!("file-{}", key_count);
-            FileTree::File(File {
-                key,
-                file_type: "Zip".to_string(),
-                path: filepath.clone(),
-                name: f.clone(),
-            })
+    if children.is_empty() {
+        parent.remove(position);
+        return true;
+    }
+    let mut key_count = max_key_number(tree);
+    renumber_keys(&mut children, &mut key_count);
+    if let Some((parent, position)) = find_lazy_directory(tree, path) {
+        if let FileTree::Directory(dir) = &mut parent[position] {
+            dir.children = children;
+            dir.lazy = false;
+        }
+    }
+    true
+}
+
+/// ツリーに未展開の内側のアーカイブ `path` があるか
+fn has_lazy_directory(tree: &[FileTree], path: &str) -> bool {
+    tree.iter().any(|node| match node {
+        FileTree::Directory(dir) => {
+            (dir.lazy && dir.path == path) || has_lazy_directory(&dir.children, path)
+        }
+        FileTree::File(_) => false,
+    })
+}
+
+/// 未展開の内側のアーカイブ `path` を含むツリーと、その中の位置
+fn find_lazy_directory<'a>(
+    tree: &'a mut Vec<FileTree>,
+    path: &str,
+) -> Option<(&'a mut Vec<FileTree>, usize)> {
+    let position = tree.iter().position(|node| match node {
+        FileTree::Directory(dir) => dir.lazy && dir.path == path,
+        FileTree::File(_) => false,
+    });
+    if let Some(position) = position {
+        return Some((tree, position));
+    }
+    tree.iter_mut().find_map(|node| match node {
+        FileTree::Directory(dir) => find_lazy_directory(&mut dir.children, path),
+        FileTree::File(_) => None,
+    })
+}
+
+/// ツリー内のファイルのキー (`file-<番号>`) の最大の番号
+fn max_key_number(tree: &[FileTree]) -> i32 {
+    tree.iter()
+        .map(|node| match node {
+            FileTree::Directory(dir) => max_key_number(&dir.children),
+            FileTree::File(file) => file
+                .key
+                .strip_prefix("file-")
+                .and_then(|n| n.parse().ok())
+                .unwrap_or_default(),
         })
-        .collect()
+        .max()
+        .unwrap_or_default()
+}
+
+fn renumber_keys(tree: &mut [FileTree], key_count: &mut i32) {
+    for node in tree {
+        match node {
+            FileTree::Directory(dir) => renumber_keys(&mut dir.children, key_count),
+            FileTree::File(file) => {
+                *key_count += 1;
+                file.key = format!("file-{}", key_count);
+            }
+        }
+    }
+}
+
+/// 展開済みの内側のアーカイブのパス（外側から順）
+/// ツリーを作り直したときに、開いていた内側のアーカイブを展開し直すために使う
+pub(crate) fn expanded_archive_paths(tree: &[FileTree]) -> Vec<String> {
+    let mut paths = vec![];
+    for node in tree {
+        if let FileTree::Directory(dir) = node {
+            if dir.path.contains(NESTED_ARCHIVE_SEPARATOR) && !dir.lazy {
+                paths.push(dir.path.clone());
+            }
+            paths.extend(expanded_archive_paths(&dir.children));
+        }
+    }
+    paths
+}
+
+/// アーカイブのエントリ一覧からディレクトリ構造の中間表現を作る
+/// ディレクトリエントリを持たないアーカイブもあるため、ファイルのパスから階層を組み立てる
+fn build_archive_dir_node(archive: &dyn ArchiveReader, depth: usize) -> ArchiveDirNode {
+    let mut root = ArchiveDirNode::default();
+    for entry in archive.entries() {
+        let is_nested_archive =
+            depth < MAX_NESTED_ARCHIVE_DEPTH && ArchiveFormat::from_path(&entry.name).is_some();
+        if entry.is_dir || !(is_executable_file(&entry.name) || is_nested_archive) {
+            continue;
+        }
+        let segments: Vec<&str> = entry.name.split('/').filter(|s| !s.is_empty()).collect();
+        let Some((_, dir_segments)) = segments.split_last() else {
+            continue;
+        };
+        let node = dir_segments.iter().fold(&mut root, |node, segment| {
+            node.dirs.entry(segment.to_string()).or_default()
+        });
+        if is_nested_archive {
+            node.archives.push(entry.name.clone());
+        } else {
+            node.files.push(entry.name.clone());
+        }
+    }
+    root
 }
 
 pub(crate) fn find_first_file(tree: &Vec<FileTree>) -> Option<File> {
@@ -289,11 +761,7 @@ pub(crate) fn find_key_in_tree(tree: &Vec<FileTree>, key: &String) -> Option<Fil
                     return Some(file.clone());
                 }
             }
-            FileTree::Directory(Directory {
-                path: _,
-                name: _,
-                children,
-            }) => {
+            FileTree::Directory(Directory { children, .. }) => {
                 let file = find_key_in_tree(children, key);
                 if file.is_some() {
                     return file;
@@ -312,11 +780,7 @@ pub(crate) fn find_path_in_tree(tree: &Vec<FileTree>, path: &String) -> Option<F
                     return Some(file.clone());
                 }
             }
-            FileTree::Directory(Directory {
-                path: _,
-                name: _,
-                children,
-            }) => {
+            FileTree::Directory(Directory { children, .. }) => {
                 let file = find_key_in_tree(children, path);
                 if file.is_some() {
                     return file;
@@ -327,18 +791,25 @@ pub(crate) fn find_path_in_tree(tree: &Vec<FileTree>, path: &String) -> Option<F
     None
 }
 
+/// `file` と同じファイル（パス・エントリ名・種類が同じもの）をツリーから探す
+/// ツリーを作り直してキーが変わったときに、表示中のファイルを探し直すために使う
+pub(crate) fn find_file_in_tree(tree: &[FileTree], file: &File) -> Option<File> {
+    tree.iter().find_map(|node| match node {
+        FileTree::Directory(dir) => find_file_in_tree(&dir.children, file),
+        FileTree::File(f) => {
+            (f.path == file.path && f.name == file.name && f.file_type == file.file_type)
+                .then(|| f.clone())
+        }
+    })
+}
+
 pub(crate) fn get_next_in_tree(viewing: &String, tree: &[FileTree]) -> Option<File> {
     let (files, dirs): (Vec<_>, Vec<_>) = tree.iter().partition(|v| matches!(v, FileTree::File(_)));
     let files: Vec<_> = files
         .iter()
-        .map(|v| match v {
-            FileTree::File(file) => file.clone(),
-            _ => File {
-                key: "".to_string(),
-                file_type: "".to_string(),
-                path: "".to_string(),
-                name: "".to_string(),
-            },
+        .filter_map(|v| match v {
+            FileTree::File(file) => Some(file.clone()),
+            _ => None,
         })
         .collect();
     let dirs: Vec<_> = dirs
@@ -349,6 +820,7 @@ pub(crate) fn get_next_in_tree(viewing: &String, tree: &[FileTree]) -> Option<Fi
                 path: "".to_string(),
                 name: "".to_string(),
                 children: vec![],
+                lazy: false,
             },
         })
         .collect();
@@ -372,14 +844,9 @@ pub(crate) fn get_prev_in_tree(viewing: &String, tree: &[FileTree]) -> Option<Fi
     let (files, dirs): (Vec<_>, Vec<_>) = tree.iter().partition(|v| matches!(v, FileTree::File(_)));
     let files: Vec<_> = files
         .iter()
-        .map(|v| match v {
-            FileTree::File(file) => file.clone(),
-            _ => File {
-                key: "".to_string(),
-                file_type: "".to_string(),
-                path: "".to_string(),
-                name: "".to_string(),
-            },
+        .filter_map(|v| match v {
+            FileTree::File(file) => Some(file.clone()),
+            _ => None,
         })
         .collect();
     let dirs: Vec<_> = dirs
@@ -390,6 +857,7 @@ pub(crate) fn get_prev_in_tree(viewing: &String, tree: &[FileTree]) -> Option<Fi
                 path: "".to_string(),
                 name: "".to_string(),
                 children: vec![],
+                lazy: false,
             },
         })
         .collect();
@@ -408,3 +876,333 @@ pub(crate) fn get_prev_in_tree(viewing: &String, tree: &[FileTree]) -> Option<Fi
     }
     None
 }
+
+/// ツリーのファイルを表示と同じ順（深さ優先）に並べる
+fn flatten_tree_files(tree: &[FileTree]) -> Vec<&File> {
+    let mut files = vec![];
+    for node in tree {
+        match node {
+            FileTree::Directory(dir) => files.extend(flatten_tree_files(&dir.children)),
+            FileTree::File(file) => files.push(file),
+        }
+    }
+    files
+}
+
+/// ツリー全体を深さ優先でたどって前後のファイルを取得する
+/// `wrap` が false の場合、ツリーの先頭・末尾を越える移動は None を返す
+fn step_in_whole_tree(
+    viewing: &String,
+    tree: &[FileTree],
+    forward: bool,
+    wrap: bool,
+) -> Option<File> {
+    let files = flatten_tree_files(tree);
+    let idx = files.iter().position(|v| v.key == *viewing)?;
+    let length = files.len();
+    let next_idx = match (forward, wrap) {
+        (true, true) => (idx + 1) % length,
+        (true, false) => Some(idx + 1).filter(|&i| i < length)?,
+        (false, true) => (idx + length - 1) % length,
+        (false, false) => idx.checked_sub(1)?,
+    };
+    files.get(next_idx).map(|file| (*file).clone())
+}
+
+/// ツリー全体を表示と同じ順（深さ優先）に並べたときの要素
+enum TreeItem<'a> {
+    File(&'a File),
+    /// 未展開の内側のアーカイブのパス
+    Lazy(&'a str),
+}
+
+fn flatten_tree_items(tree: &[FileTree]) -> Vec<TreeItem<'_>> {
+    let mut items = vec![];
+    for node in tree {
+        match node {
+            FileTree::Directory(dir) if dir.lazy => items.push(TreeItem::Lazy(&dir.path)),
+            FileTree::Directory(dir) => items.extend(flatten_tree_items(&dir.children)),
+            FileTree::File(file) => items.push(TreeItem::File(file)),
+        }
+    }
+    items
+}
+
+/// `mode` でたどったときに、表示中のファイルの次（`forward` が false なら前）が未展開の内側のアーカイブなら
+/// そのパスを返す。フォルダ内だけを移動するモードでは None
+pub(crate) fn find_lazy_directory_on_step(
+    viewing: &String,
+    tree: &[FileTree],
+    mode: NavigationMode,
+    forward: bool,
+) -> Option<String> {
+    let wrap = match mode {
+        NavigationMode::Folder => return None,
+        NavigationMode::TreeWrap => true,
+        NavigationMode::TreeStop => false,
+    };
+    let items = flatten_tree_items(tree);
+    let idx = items
+        .iter()
+        .position(|item| matches!(item, TreeItem::File(file) if file.key == *viewing))?;
+    let length = items.len();
+    let next_idx = match (forward, wrap) {
+        (true, true) => (idx + 1) % length,
+        (true, false) => Some(idx + 1).filter(|&i| i < length)?,
+        (false, true) => (idx + length - 1) % length,
+        (false, false) => idx.checked_sub(1)?,
+    };
+    match items[next_idx] {
+        TreeItem::File(_) => None,
+        TreeItem::Lazy(path) => Some(path.to_string()),
+    }
+}
+
+/// `mode` に従って次のファイルを取得する
+pub(crate) fn get_next_file(
+    viewing: &String,
+    tree: &[FileTree],
+    mode: NavigationMode,
+) -> Option<File> {
+    match mode {
+        NavigationMode::Folder => get_next_in_tree(viewing, tree),
+        NavigationMode::TreeWrap => step_in_whole_tree(viewing, tree, true, true),
+        NavigationMode::TreeStop => step_in_whole_tree(viewing, tree, true, false),
+    }
+}
+
+/// `mode` に従って前のファイルを取得する
+pub(crate) fn get_prev_file(
+    viewing: &String,
+    tree: &[FileTree],
+    mode: NavigationMode,
+) -> Option<File> {
+    match mode {
+        NavigationMode::Folder => get_prev_in_tree(viewing, tree),
+        NavigationMode::TreeWrap => step_in_whole_tree(viewing, tree, false, true),
+        NavigationMode::TreeStop => step_in_whole_tree(viewing, tree, false, false),
+    }
+}
+
+/// 先読みする前後のファイル数
+const PREFETCH_COUNT: usize = 3;
+
+/// 表示中のファイルの前後 `PREFETCH_COUNT` 件の画像を、次のファイルを優先して前後交互に返す
+/// 動画は Range リクエストで必要な範囲だけを読むため含めない
+/// 前後のファイルはページ送りと同じ `mode` でたどる
+pub(crate) fn neighbor_images(
+    viewing: &File,
+    tree: &[FileTree],
+    mode: NavigationMode,
+) -> Vec<File> {
+    let mut targets: Vec<File> = vec![];
+    let mut next_key = viewing.key.clone();
+    let mut prev_key = viewing.key.clone();
+    // 次のファイルを優先して前後を交互に読み込む
+    for _ in 0..PREFETCH_COUNT {
+        for file in [
+            get_next_file(&next_key, tree, mode),
+            get_prev_file(&prev_key, tree, mode),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if file.key != viewing.key && targets.iter().all(|t| t.key != file.key) {
+                targets.push(file);
+            }
+        }
+        next_key = get_next_file(&next_key, tree, mode).map_or(next_key, |f| f.key);
+        prev_key = get_prev_file(&prev_key, tree, mode).map_or(prev_key, |f| f.key);
+    }
+    targets.retain(|file| match file.file_type {
+        FileType::Image => true,
+        FileType::Zip => is_image_file(&file.name),
+        FileType::Video => false,
+    });
+    targets
+}
+
+/// 表示中のファイルの前後の画像を読み込み、メモリキャッシュに載せる
+/// アーカイブ内のエントリの展開と、表示用に縮小した画像の作成（デコードと縮小）もここで済ませておく
+pub(crate) fn prefetch_neighbors(
+    viewing: &File,
+    tree: &[FileTree],
+    mode: NavigationMode,
+    state: &AppState,
+) {
+    let targets = neighbor_images(viewing, tree, mode);
+    if targets.is_empty() {
+        return;
+    }
+
+    let archive_options = state.archive_options.clone();
+    let archive_cache = state.archive_cache.clone();
+    let media_cache = state.media_cache.clone();
+    tokio::spawn(async move {
+        let archive_options = archive_options.read().await.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            for file in targets {
+                let entry = (file.file_type == FileType::Zip).then_some(file.name.as_str());
+                // 読み込めないファイルは表示時に改めてエラーを返すため、ここでは無視する
+                let _ = match media_cache.display_size() {
+                    Some(max_size) => media_cache.load_display(
+                        &file.path,
+                        entry,
+                        max_size,
+                        &archive_options,
+                        &archive_cache,
+                    ),
+                    None => media_cache.load_viewable(
+                        &file.path,
+                        entry,
+                        &archive_options,
+                        &archive_cache,
+                    ),
+                };
+            }
+        })
+        .await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(key: &str, name: &str) -> FileTree {
+        FileTree::File(File {
+            key: key.to_string(),
+            file_type: FileType::Zip,
+            path: "a.zip".to_string(),
+            name: name.to_string(),
+            is_video: false,
+        })
+    }
+
+    fn dir(path: &str, children: Vec<FileTree>, lazy: bool) -> FileTree {
+        FileTree::Directory(Directory {
+            path: path.to_string(),
+            name: path.to_string(),
+            children,
+            lazy,
+        })
+    }
+
+    #[test]
+    fn test_set_lazy_directory_children() {
+        let mut tree = vec![
+            dir("a.zip!/b.zip", vec![], true),
+            dir("a.zip!/c.zip", vec![], true),
+            file("file-1", "1.jpg"),
+        ];
+        assert!(set_lazy_directory_children(
+            &mut tree,
+            "a.zip!/b.zip",
+            vec![file("file-1", "b/1.jpg"), file("file-2", "b/2.jpg")],
+        ));
+        // 加えたファイルのキーは既存のキーと重複しない
+        let keys: Vec<_> = flatten_tree_files(&tree)
+            .iter()
+            .map(|f| f.key.clone())
+            .collect();
+        assert_eq!(keys, vec!["file-2", "file-3", "file-1"]);
+        assert_eq!(expanded_archive_paths(&tree), vec!["a.zip!/b.zip"]);
+
+        // 展開済みのものは対象外で、中身が空なら取り除く
+        assert!(!set_lazy_directory_children(
+            &mut tree,
+            "a.zip!/b.zip",
+            vec![]
+        ));
+        assert!(set_lazy_directory_children(
+            &mut tree,
+            "a.zip!/c.zip",
+            vec![]
+        ));
+        assert_eq!(tree.len(), 2);
+    }
+
+    /// 深さ優先の順で file-1 〜 file-5 が並ぶ、空のフォルダと入れ子のフォルダを含むツリー
+    fn nested_tree() -> Vec<FileTree> {
+        vec![
+            dir(
+                "a",
+                vec![file("file-1", "1.jpg"), file("file-2", "2.jpg")],
+                false,
+            ),
+            dir("empty", vec![], false),
+            dir(
+                "b",
+                vec![
+                    dir("b/c", vec![file("file-3", "3.jpg")], false),
+                    file("file-4", "4.jpg"),
+                ],
+                false,
+            ),
+            file("file-5", "5.jpg"),
+        ]
+    }
+
+    fn next_key(viewing: &str, tree: &[FileTree], mode: NavigationMode) -> Option<String> {
+        get_next_file(&viewing.to_string(), tree, mode).map(|f| f.key)
+    }
+
+    fn prev_key(viewing: &str, tree: &[FileTree], mode: NavigationMode) -> Option<String> {
+        get_prev_file(&viewing.to_string(), tree, mode).map(|f| f.key)
+    }
+
+    #[test]
+    fn test_navigation_folder() {
+        let tree = nested_tree();
+        let mode = NavigationMode::Folder;
+        // 同じフォルダ内だけを移動し、末尾の次は先頭に戻る
+        assert_eq!(next_key("file-1", &tree, mode).as_deref(), Some("file-2"));
+        assert_eq!(next_key("file-2", &tree, mode).as_deref(), Some("file-1"));
+        assert_eq!(prev_key("file-1", &tree, mode).as_deref(), Some("file-2"));
+        // 入れ子のフォルダ内のファイルも、そのフォルダの中だけを移動する
+        assert_eq!(next_key("file-3", &tree, mode).as_deref(), Some("file-3"));
+        assert_eq!(prev_key("file-4", &tree, mode).as_deref(), Some("file-4"));
+        assert_eq!(next_key("file-9", &tree, mode), None);
+    }
+
+    #[test]
+    fn test_navigation_tree_wrap() {
+        let tree = nested_tree();
+        let mode = NavigationMode::TreeWrap;
+        // 空のフォルダを飛ばし、入れ子のフォルダに入って出る
+        assert_eq!(next_key("file-2", &tree, mode).as_deref(), Some("file-3"));
+        assert_eq!(next_key("file-3", &tree, mode).as_deref(), Some("file-4"));
+        assert_eq!(next_key("file-4", &tree, mode).as_deref(), Some("file-5"));
+        assert_eq!(prev_key("file-3", &tree, mode).as_deref(), Some("file-2"));
+        assert_eq!(prev_key("file-5", &tree, mode).as_deref(), Some("file-4"));
+        // ツリーの先頭・末尾を越えると反対側に戻る
+        assert_eq!(next_key("file-5", &tree, mode).as_deref(), Some("file-1"));
+        assert_eq!(prev_key("file-1", &tree, mode).as_deref(), Some("file-5"));
+    }
+
+    #[test]
+    fn test_navigation_tree_stop() {
+        let tree = nested_tree();
+        let mode = NavigationMode::TreeStop;
+        assert_eq!(next_key("file-2", &tree, mode).as_deref(), Some("file-3"));
+        assert_eq!(prev_key("file-3", &tree, mode).as_deref(), Some("file-2"));
+        // ツリーの先頭・末尾で止まる
+        assert_eq!(next_key("file-5", &tree, mode), None);
+        assert_eq!(prev_key("file-1", &tree, mode), None);
+    }
+
+    #[test]
+    fn test_navigation_empty_tree() {
+        let empty = vec![dir("empty", vec![], false)];
+        for tree in [vec![], empty] {
+            for mode in [
+                NavigationMode::Folder,
+                NavigationMode::TreeWrap,
+                NavigationMode::TreeStop,
+            ] {
+                assert_eq!(next_key("file-1", &tree, mode), None);
+                assert_eq!(prev_key("file-1", &tree, mode), None);
+            }
+        }
+    }
+}
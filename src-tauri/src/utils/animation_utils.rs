@@ -0,0 +1,239 @@
+//! アニメーション画像 (GIF / APNG / アニメーション WebP) のフレーム
+//!
+//! WebView に任せた再生では一時停止やコマ送り、フレームの書き出しができない。
+//! アニメーションを全フレームに展開し、フレーム数・表示時間・ループ回数と、
+//! 指定したフレームの PNG を返す
+//!
+//! 展開したフレームは直近の 1 枚のアニメーションだけメモリに保持し、
+//! コマ送りのたびにデコードし直さないようにする。展開後のサイズが `MAX_ANIMATION_BYTES` を
+//! 超えるアニメーションは展開をやめてエラーにする
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frames, ImageFormat, RgbaImage};
+use serde::Serialize;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+/// 展開したフレームの合計サイズ（幅 × 高さ × 4 バイト × フレーム数）の上限
+/// 長いアニメーションの全フレームを展開してメモリを使い果たさないようにする
+const MAX_ANIMATION_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// アニメーションの情報
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AnimationInfo {
+    pub width: u32,
+    pub height: u32,
+    pub frame_count: u32,
+    /// 再生回数（0 は無限に繰り返す）
+    pub loop_count: u32,
+    /// 各フレームの表示時間 (ミリ秒)
+    pub delays: Vec<u32>,
+}
+
+/// 全フレームに展開したアニメーション
+pub(crate) struct Animation {
+    pub info: AnimationInfo,
+    /// 前のフレームと合成済みの各フレーム
+    pub frames: Vec<RgbaImage>,
+}
+
+/// アニメーション画像を全フレームに展開する
+pub(crate) fn decode_animation(data: &[u8]) -> Result<Animation> {
+    decode_animation_within(data, MAX_ANIMATION_BYTES)
+}
+
+/// アニメーション画像を全フレームに展開する
+/// 展開したフレームの合計サイズが `max_bytes` を超えた時点で展開をやめてエラーにする
+fn decode_animation_within(data: &[u8], max_bytes: u64) -> Result<Animation> {
+    let format = image::guess_format(data).context("failed to read image")?;
+    let frames: Frames = match format {
+        ImageFormat::Gif => GifDecoder::new(Cursor::new(data))?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(data))?;
+            if !decoder.is_apng()? {
+                bail!("not an animated image: {:?}", format);
+            }
+            decoder.apng()?.into_frames()
+        }
+        ImageFormat::WebP => WebPDecoder::new(Cursor::new(data))?.into_frames(),
+        _ => bail!("not an animated image: {:?}", format),
+    };
+    let mut decoded = vec![];
+    let mut total_bytes = 0u64;
+    for frame in frames {
+        let frame = frame.context("failed to decode animation")?;
+        total_bytes += frame.buffer().as_raw().len() as u64;
+        if total_bytes > max_bytes {
+            bail!(
+                "animation is too large to expand: exceeds {} MiB at frame {}",
+                max_bytes / (1024 * 1024),
+                decoded.len() + 1
+            );
+        }
+        decoded.push(frame);
+    }
+    let frames = decoded;
+    let first = frames
+        .first()
+        .ok_or_else(|| anyhow!("animation has no frames"))?;
+
+    let info = AnimationInfo {
+        width: first.buffer().width(),
+        height: first.buffer().height(),
+        frame_count: frames.len() as u32,
+        loop_count: read_loop_count(data, format),
+        delays: frames
+            .iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                numer / denom.max(1)
+            })
+            .collect(),
+    };
+    Ok(Animation {
+        info,
+        frames: frames
+            .into_iter()
+            .map(|frame| frame.into_buffer())
+            .collect(),
+    })
+}
+
+/// 再生回数を読み取る（0 は無限に繰り返す）
+/// `image` のデコーダは再生回数を返さないため、各形式のチャンクを直接探す
+fn read_loop_count(data: &[u8], format: ImageFormat) -> u32 {
+    let find = |marker: &[u8]| {
+        data.windows(marker.len())
+            .position(|w| w == marker)
+            .map(|pos| &data[pos + marker.len()..])
+    };
+    let read_u16_le = |bytes: &[u8]| Some(u16::from_le_bytes(bytes.get(..2)?.try_into().ok()?));
+    match format {
+        // NETSCAPE2.0 拡張の繰り返し回数（初回の再生は含まない）。拡張がなければ 1 回だけ再生する
+        ImageFormat::Gif => match find(b"NETSCAPE2.0\x03\x01").and_then(read_u16_le) {
+            Some(0) => 0,
+            Some(repeat) => repeat as u32 + 1,
+            None => 1,
+        },
+        // acTL チャンクの num_plays（フレーム数の後ろ）
+        ImageFormat::Png => find(b"acTL")
+            .and_then(|chunk| Some(u32::from_be_bytes(chunk.get(4..8)?.try_into().ok()?)))
+            .unwrap_or(0),
+        // ANIM チャンクのループ回数（チャンクサイズと背景色の後ろ）
+        ImageFormat::WebP => find(b"ANIM")
+            .and_then(|chunk| read_u16_le(chunk.get(8..)?))
+            .map_or(0, u32::from),
+        _ => 1,
+    }
+}
+
+/// 展開したアニメーションの置き場所
+/// 展開中はこの置き場所だけをロックし、同じ画像の要求だけを待たせる
+type AnimationSlot = Arc<Mutex<Option<Arc<Animation>>>>;
+
+/// 展開したアニメーションのキャッシュ（直近の 1 枚のみ保持する）
+#[derive(Default)]
+pub struct AnimationCache {
+    /// 直近に展開したアニメーション (キー, アニメーションの置き場所)
+    current: Mutex<Option<(String, AnimationSlot)>>,
+}
+
+impl AnimationCache {
+    /// アニメーションの情報を取得する
+    /// キャッシュになければ `load_source` で元の画像を読み込んで展開する
+    pub(crate) fn get_info(
+        &self,
+        key: &str,
+        load_source: impl FnOnce() -> Result<Arc<Vec<u8>>>,
+    ) -> Result<AnimationInfo> {
+        Ok(self.get_animation(key, load_source)?.info.clone())
+    }
+
+    /// 指定したフレーム（0 始まり）を PNG で取得する
+    pub(crate) fn get_frame_png(
+        &self,
+        key: &str,
+        index: u32,
+        load_source: impl FnOnce() -> Result<Arc<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        let animation = self.get_animation(key, load_source)?;
+        let frame = animation
+            .frames
+            .get(index as usize)
+            .ok_or_else(|| anyhow!("frame out of range: {}", index))?;
+        let mut buf = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(frame.clone()).write_to(&mut buf, ImageFormat::Png)?;
+        Ok(buf.into_inner())
+    }
+
+    /// 展開済みのアニメーションを取得する
+    /// 展開中は置き場所のロックを保持し、同じ画像の並行した要求で展開が重複しないようにする
+    /// キャッシュ全体のロックは置き場所の取得の間だけ保持し、別の画像の要求を待たせない
+    fn get_animation(
+        &self,
+        key: &str,
+        load_source: impl FnOnce() -> Result<Arc<Vec<u8>>>,
+    ) -> Result<Arc<Animation>> {
+        let slot = {
+            let mut current = self.current.lock().map_err(|e| anyhow!("{}", e))?;
+            match current.as_ref() {
+                Some((current_key, slot)) if current_key == key => slot.clone(),
+                // 古いアニメーションの置き場所を手放し、使い終わったら解放されるようにする
+                _ => {
+                    let slot = AnimationSlot::default();
+                    *current = Some((key.to_string(), slot.clone()));
+                    slot
+                }
+            }
+        };
+        let mut animation = slot.lock().map_err(|e| anyhow!("{}", e))?;
+        if let Some(animation) = animation.as_ref() {
+            return Ok(animation.clone());
+        }
+        let decoded = Arc::new(decode_animation(&load_source()?)?);
+        *animation = Some(decoded.clone());
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_loop_count() {
+        let gif = b"GIF89a....!\xFF\x0BNETSCAPE2.0\x03\x01\x02\x00\x00";
+        assert_eq!(read_loop_count(gif, ImageFormat::Gif), 3);
+        let gif = b"GIF89a....!\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00";
+        assert_eq!(read_loop_count(gif, ImageFormat::Gif), 0);
+        assert_eq!(read_loop_count(b"GIF89a....", ImageFormat::Gif), 1);
+        let png = b"\x89PNG....\x00\x00\x00\x08acTL\x00\x00\x00\x0A\x00\x00\x00\x05";
+        assert_eq!(read_loop_count(png, ImageFormat::Png), 5);
+        let webp = b"RIFF....WEBPVP8X....ANIM\x06\x00\x00\x00\xFF\xFF\xFF\xFF\x04\x00";
+        assert_eq!(read_loop_count(webp, ImageFormat::WebP), 4);
+    }
+
+    #[test]
+    fn test_decode_animation_within_budget() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Delay, Frame};
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            let frames = (0..3).map(|i| {
+                let buffer = RgbaImage::from_pixel(10, 10, image::Rgba([i * 80, 0, 0, 255]));
+                Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(100, 1))
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+        // 1 フレームは 10 × 10 × 4 = 400 バイト
+        let animation = decode_animation_within(&gif, 1200).unwrap();
+        assert_eq!(animation.info.frame_count, 3);
+        assert_eq!(animation.info.delays, vec![100, 100, 100]);
+        assert!(decode_animation_within(&gif, 1199).is_err());
+    }
+}
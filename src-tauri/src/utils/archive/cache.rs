@@ -0,0 +1,85 @@
+//! 開いたアーカイブのハンドルキャッシュ
+//!
+//! ページ送りのたびにアーカイブを開き直すと、エントリ数の多い ZIP やネットワークドライブでは
+//! セントラルディレクトリの解析だけで時間がかかる。解析済みのハンドルを LRU で保持して使い回す
+
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::{open_archive, root_archive_path, ArchiveOptions, ArchiveReader};
+use crate::utils::file_utils::normalize_path;
+
+/// 保持するハンドルの最大数
+const MAX_CACHED_ARCHIVES: usize = 8;
+
+/// キャッシュのキー（アーカイブのパス + 実ファイルの更新日時）
+/// ファイルが更新されると更新日時が変わり、古いハンドルは使われなくなる
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ArchiveCacheKey {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+type SharedArchive = Arc<Mutex<Box<dyn ArchiveReader>>>;
+
+/// アーカイブハンドルの LRU キャッシュ
+#[derive(Default)]
+pub struct ArchiveCache {
+    /// 末尾ほど最近使われたハンドル
+    entries: Mutex<Vec<(ArchiveCacheKey, SharedArchive)>>,
+}
+
+impl ArchiveCache {
+    /// キャッシュ済みのハンドル（なければ開いたハンドル）で `f` を実行する
+    /// ハンドル単位でロックするため、別のアーカイブの読み込みはブロックしない
+    pub(crate) fn with_archive<T>(
+        &self,
+        path: &str,
+        options: &ArchiveOptions,
+        f: impl FnOnce(&mut dyn ArchiveReader) -> Result<T>,
+    ) -> Result<T> {
+        let archive = self.get_or_open(path, options)?;
+        let mut archive = archive.lock().map_err(|e| anyhow!("{}", e))?;
+        f(archive.as_mut())
+    }
+
+    fn get_or_open(&self, path: &str, options: &ArchiveOptions) -> Result<SharedArchive> {
+        let key = ArchiveCacheKey {
+            path: path.to_string(),
+            modified: std::fs::metadata(root_archive_path(path))
+                .and_then(|m| m.modified())
+                .ok(),
+        };
+
+        {
+            let mut entries = self.entries.lock().map_err(|e| anyhow!("{}", e))?;
+            if let Some(pos) = entries.iter().position(|(k, _)| *k == key) {
+                let entry = entries.remove(pos);
+                let archive = entry.1.clone();
+                entries.push(entry);
+                return Ok(archive);
+            }
+        } // 開いている間は他のアーカイブの読み込みを妨げないようロックを解放
+
+        let archive: SharedArchive = Arc::new(Mutex::new(open_archive(path, options)?));
+        let mut entries = self.entries.lock().map_err(|e| anyhow!("{}", e))?;
+        // 同じパスの古いハンドル（更新前のファイルや同時に開かれたもの）は置き換える
+        entries.retain(|(k, _)| k.path != key.path);
+        entries.push((key, archive.clone()));
+        if entries.len() > MAX_CACHED_ARCHIVES {
+            let overflow = entries.len() - MAX_CACHED_ARCHIVES;
+            entries.drain(..overflow);
+        }
+        Ok(archive)
+    }
+
+    /// 指定したファイルを実体とするハンドル（内側のアーカイブを含む）を破棄する
+    /// 設定の変更やファイルの変更通知を受けた際に呼び出す
+    pub(crate) fn invalidate(&self, file_path: &str) {
+        let file_path = normalize_path(file_path);
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|(k, _)| normalize_path(root_archive_path(&k.path)) != file_path);
+        }
+    }
+}
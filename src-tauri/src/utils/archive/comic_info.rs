@@ -0,0 +1,179 @@
+//! ComicInfo.xml によるアーカイブのメタデータ
+//!
+//! CBZ などのコミックアーカイブに同梱される ComicInfo.xml からシリーズ名・巻数・作者・
+//! 読み方向などを取り出す。Explorer の一覧表示でアーカイブごとに展開し直さないよう、
+//! 解析結果は一時ディレクトリに JSON で保存して使い回す
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::spool::spool_key;
+use super::{open_archive, root_archive_path, ArchiveOptions, ArchiveReader};
+
+/// メタデータファイルのエントリ名（大文字小文字は区別しない）
+const COMIC_INFO_FILE_NAME: &str = "comicinfo.xml";
+
+/// アーカイブのメタデータ
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveMetadata {
+    /// 表示用のタイトル（シリーズ名・巻・番号またはタイトルから組み立てる）
+    pub display_title: Option<String>,
+    pub title: Option<String>,
+    pub series: Option<String>,
+    pub number: Option<String>,
+    pub volume: Option<i32>,
+    pub writer: Option<String>,
+    pub summary: Option<String>,
+    /// 右から左に読む（Manga = YesAndRightToLeft）
+    pub right_to_left: bool,
+}
+
+/// 表示用のタイトルを組み立てる
+/// シリーズ名があれば「シリーズ名 Vol.巻 #番号」、なければタイトルを返す
+fn make_display_title(
+    title: Option<&String>,
+    series: Option<&String>,
+    volume: Option<i32>,
+    number: Option<&String>,
+) -> Option<String> {
+    let Some(series) = series else {
+        return title.cloned();
+    };
+    let mut display_title = series.clone();
+    if let Some(volume) = volume {
+        display_title.push_str(&format!(" Vol.{}", volume));
+    }
+    if let Some(number) = number {
+        display_title.push_str(&format!(" #{}", number));
+    }
+    Some(display_title)
+}
+
+/// ComicInfo.xml の要素（解析用）
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct ComicInfoXml {
+    title: Option<String>,
+    series: Option<String>,
+    number: Option<String>,
+    volume: Option<String>,
+    writer: Option<String>,
+    summary: Option<String>,
+    manga: Option<String>,
+}
+
+/// ComicInfo.xml の内容を解析する
+fn parse_comic_info(xml: &str) -> Result<ArchiveMetadata> {
+    let info: ComicInfoXml =
+        quick_xml::de::from_str(xml).context("failed to parse ComicInfo.xml")?;
+    // 空要素は未指定として扱う
+    let non_empty = |v: Option<String>| v.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    let title = non_empty(info.title);
+    let series = non_empty(info.series);
+    let number = non_empty(info.number);
+    let volume = info.volume.and_then(|v| v.trim().parse().ok());
+    Ok(ArchiveMetadata {
+        display_title: make_display_title(title.as_ref(), series.as_ref(), volume, number.as_ref()),
+        title,
+        series,
+        number,
+        volume,
+        writer: non_empty(info.writer),
+        summary: non_empty(info.summary),
+        right_to_left: info.manga.as_deref().map(str::trim) == Some("YesAndRightToLeft"),
+    })
+}
+
+/// 開いているアーカイブから ComicInfo.xml を探して解析する
+/// ルート直下のものを優先し、なければサブフォルダ内のものを使う
+pub(crate) fn read_comic_info(archive: &mut dyn ArchiveReader) -> Result<Option<ArchiveMetadata>> {
+    let entry_name = archive
+        .entries()
+        .iter()
+        .filter(|e| {
+            !e.is_dir
+                && e.name
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|n| n.eq_ignore_ascii_case(COMIC_INFO_FILE_NAME))
+        })
+        .min_by_key(|e| e.name.matches('/').count())
+        .map(|e| e.name.clone());
+    let Some(entry_name) = entry_name else {
+        return Ok(None);
+    };
+    let data = archive.read_entry(&entry_name)?;
+    parse_comic_info(&String::from_utf8_lossy(&data)).map(Some)
+}
+
+/// 解析結果の保存先ディレクトリ
+fn metadata_dir() -> PathBuf {
+    std::env::temp_dir()
+        .join("simple-image-viewer")
+        .join("archive-metadata")
+}
+
+/// 解析結果の保存先のパス
+fn metadata_cache_path(path: &str) -> Result<PathBuf> {
+    let key = spool_key(root_archive_path(path), &format!("metadata|{}", path))?;
+    Ok(metadata_dir().join(format!("{}.json", key)))
+}
+
+/// 保存済みの解析結果を返す（アーカイブは開かない）。まだ解析していなければ None
+pub(crate) fn cached_archive_metadata(path: &str) -> Option<Option<ArchiveMetadata>> {
+    let cached = std::fs::read(metadata_cache_path(path).ok()?).ok()?;
+    serde_json::from_slice(&cached).ok()
+}
+
+/// アーカイブのメタデータを取得する。ComicInfo.xml がなければ None
+/// 一度解析した結果はアーカイブが更新されるまで使い回す
+pub(crate) fn get_archive_metadata(
+    path: &str,
+    options: &ArchiveOptions,
+) -> Result<Option<ArchiveMetadata>> {
+    if let Some(metadata) = cached_archive_metadata(path) {
+        return Ok(metadata);
+    }
+
+    let cache_path = metadata_cache_path(path)?;
+    let dir = metadata_dir();
+    let mut archive = open_archive(path, options)?;
+    let metadata = read_comic_info(archive.as_mut())?;
+
+    // ComicInfo.xml がない場合も null として保存し、次回の探索を省く
+    std::fs::create_dir_all(&dir)?;
+    let part_path = cache_path.with_extension("part");
+    std::fs::write(&part_path, serde_json::to_vec(&metadata)?)?;
+    std::fs::rename(&part_path, &cache_path)?;
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comic_info() {
+        let xml = r#"<?xml version="1.0"?>
+<ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <Title>はじまり</Title>
+  <Series>サンプル</Series>
+  <Number>3</Number>
+  <Volume>2</Volume>
+  <Writer>作者</Writer>
+  <Summary></Summary>
+  <Manga>YesAndRightToLeft</Manga>
+  <Pages>
+    <Page Image="0" Type="FrontCover" />
+    <Page Image="1" DoublePage="true" />
+  </Pages>
+</ComicInfo>"#;
+        let metadata = parse_comic_info(xml).unwrap();
+        assert_eq!(metadata.series.as_deref(), Some("サンプル"));
+        assert_eq!(metadata.volume, Some(2));
+        assert_eq!(metadata.summary, None);
+        assert!(metadata.right_to_left);
+        assert_eq!(metadata.display_title.as_deref(), Some("サンプル Vol.2 #3"));
+    }
+}
@@ -0,0 +1,87 @@
+//! アーカイブの表紙画像
+//!
+//! Explorer のサムネイルや閲覧履歴は画像ファイルのパスを前提にしているため、
+//! アーカイブ内の最初の画像を縮小して一時ディレクトリに保存し、そのパスを表紙として扱う
+//!
+//! 圧縮 TAR とソリッドの 7z / RAR は、最初の画像を取り出すだけでもアーカイブ全体の展開が必要になるため、
+//! 一覧を表示するたびに展開しないよう表紙を作らない
+
+use anyhow::{Context, Result};
+use image::ImageFormat;
+use std::path::PathBuf;
+
+use super::spool::spool_key;
+use super::{
+    open_archive, root_archive_path, ArchiveFormat, ArchiveOptions, NESTED_ARCHIVE_SEPARATOR,
+};
+use crate::utils::file_utils::is_image_file;
+use crate::utils::image_utils::decode_image;
+
+/// 表紙画像の最大サイズ（幅・高さ）
+const COVER_MAX_SIZE: u32 = 512;
+
+/// 表紙画像の保存先ディレクトリ
+fn cover_dir() -> PathBuf {
+    std::env::temp_dir()
+        .join("simple-image-viewer")
+        .join("archive-covers")
+}
+
+/// 表紙を作るために全体の展開が必要になる形式か
+fn requires_full_extraction(path: &str) -> bool {
+    let name = path.rsplit(NESTED_ARCHIVE_SEPARATOR).next().unwrap_or(path);
+    matches!(
+        ArchiveFormat::from_path(name),
+        Some(ArchiveFormat::TarGz | ArchiveFormat::TarBz2 | ArchiveFormat::TarXz)
+    )
+}
+
+/// アーカイブの表紙画像のパスを返す
+/// 画像を含まないアーカイブと、全体の展開が必要なアーカイブでは None
+/// パスワードとファイル名の文字コードは `options` の設定で開く
+/// 一度作成した表紙はアーカイブが更新されるまで使い回す（文字コードを変えると作り直す）
+pub(crate) fn get_archive_cover(path: &str, options: &ArchiveOptions) -> Result<Option<PathBuf>> {
+    if requires_full_extraction(path) {
+        return Ok(None);
+    }
+    let tag = format!("cover|{}|{:?}", path, options.name_encoding(path));
+    let key = spool_key(root_archive_path(path), &tag)?;
+    let dir = cover_dir();
+    let cover_path = dir.join(format!("{}.jpg", key));
+    if cover_path.exists() {
+        return Ok(Some(cover_path));
+    }
+
+    let mut archive = open_archive(path, options)?;
+    if archive.is_solid() {
+        return Ok(None);
+    }
+    let first_image = archive
+        .entries()
+        .iter()
+        .filter(|e| !e.is_dir && is_image_file(&e.name))
+        .map(|e| e.name.as_str())
+        .min_by(|a, b| natord::compare(a, b))
+        .map(str::to_string);
+    let Some(first_image) = first_image else {
+        return Ok(None);
+    };
+
+    let data = archive.read_entry(&first_image)?;
+    let image = decode_image(&data)
+        .with_context(|| format!("failed to decode cover image: {}", first_image))?;
+    // 小さい画像は拡大せずそのまま使う
+    let cover = if image.width() > COVER_MAX_SIZE || image.height() > COVER_MAX_SIZE {
+        image.thumbnail(COVER_MAX_SIZE, COVER_MAX_SIZE)
+    } else {
+        image
+    }
+    .to_rgb8();
+
+    // 書き込み途中の表紙を読まれないよう、一時名で書いてからリネームする
+    std::fs::create_dir_all(&dir)?;
+    let part_path = dir.join(format!("{}.part", key));
+    cover.save_with_format(&part_path, ImageFormat::Jpeg)?;
+    std::fs::rename(&part_path, &cover_path)?;
+    Ok(Some(cover_path))
+}
@@ -0,0 +1,75 @@
+//! アーカイブ内のファイル名の文字コード判定
+//!
+//! Windows で作成された日本語の ZIP は UTF-8 フラグなしで Shift_JIS のファイル名を格納していることが多い。
+//! UTF-8 として正しく読めればそのまま使い、読めなければ Shift_JIS、それも失敗すれば
+//! ZIP 仕様上の既定である CP437 として解釈する
+
+use serde::{Deserialize, Serialize};
+
+/// ファイル名の文字コード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameEncoding {
+    Utf8,
+    ShiftJis,
+    Cp437,
+}
+
+impl NameEncoding {
+    /// 生のバイト列をこの文字コードで文字列にする（変換できない文字は置換文字になる）
+    pub(crate) fn decode(self, raw: &[u8]) -> String {
+        match self {
+            Self::Utf8 => String::from_utf8_lossy(raw).into_owned(),
+            Self::ShiftJis => encoding_rs::SHIFT_JIS.decode(raw).0.into_owned(),
+            Self::Cp437 => raw.iter().map(|&b| cp437_char(b)).collect(),
+        }
+    }
+
+    /// 生のバイト列から文字コードを推定する
+    pub(crate) fn detect(raw: &[u8]) -> Self {
+        if std::str::from_utf8(raw).is_ok() {
+            Self::Utf8
+        } else if encoding_rs::SHIFT_JIS
+            .decode_without_bom_handling_and_without_replacement(raw)
+            .is_some()
+        {
+            Self::ShiftJis
+        } else {
+            Self::Cp437
+        }
+    }
+}
+
+/// CP437 の 0x80-0xFF に対応する文字
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+fn cp437_char(b: u8) -> char {
+    if b < 0x80 {
+        b as char
+    } else {
+        CP437_HIGH
+            .chars()
+            .nth((b - 0x80) as usize)
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_decode() {
+        // "画像/001.jpg" の Shift_JIS 表現
+        let sjis = b"\x89\xe6\x91\x9c/001.jpg";
+        assert_eq!(NameEncoding::detect(sjis), NameEncoding::ShiftJis);
+        assert_eq!(NameEncoding::ShiftJis.decode(sjis), "画像/001.jpg");
+
+        assert_eq!(
+            NameEncoding::detect("画像/001.jpg".as_bytes()),
+            NameEncoding::Utf8
+        );
+        assert_eq!(CP437_HIGH.chars().count(), 128);
+        assert_eq!(NameEncoding::Cp437.decode(b"\x81ber.jpg"), "über.jpg");
+    }
+}
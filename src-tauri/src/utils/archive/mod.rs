@@ -0,0 +1,365 @@
+//! アーカイブ読み込みユーティリティ
+//!
+//! ZIP / TAR 系 / 7z / RAR など形式ごとの差異を `ArchiveReader` トレイトの裏に隠し、
+//! Viewer からはエントリ一覧の取得とエントリ単位の読み込みだけを行えるようにする
+//!
+//! アーカイブ内のアーカイブは `outer.zip!/vol1.zip!/001.jpg` のように `!/` で連結したパスで表す。
+//! 内側のアーカイブはスプールに展開してから開くため、メモリ使用量は増えず、
+//! 一時ファイルの合計サイズはスプールの上限内に収まる
+
+mod cache;
+mod comic_info;
+mod cover;
+mod encoding;
+mod rar_reader;
+mod sevenz_reader;
+mod spool;
+mod tar_reader;
+mod zip_reader;
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rar_reader::RarReader;
+use sevenz_reader::SevenZipReader;
+use spool::{get_or_create_spool, SpoolGuard};
+use tar_reader::{TarCompression, TarReader};
+use zip_reader::ZipReader;
+
+use crate::utils::format_registry::format_from_path;
+
+pub use cache::ArchiveCache;
+pub use comic_info::ArchiveMetadata;
+pub(crate) use comic_info::{cached_archive_metadata, get_archive_metadata, read_comic_info};
+pub(crate) use cover::get_archive_cover;
+pub use encoding::NameEncoding;
+
+/// アーカイブとその中のエントリを連結する区切り文字
+pub(crate) const NESTED_ARCHIVE_SEPARATOR: &str = "!/";
+
+/// アーカイブ形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarBz2,
+    TarXz,
+    SevenZip,
+    Rar,
+}
+
+impl ArchiveFormat {
+    /// ファイル名（拡張子）からアーカイブ形式を判定する
+    /// 拡張子の一覧は `format_registry` にまとめ、ファイルツリーやダイアログと同じ判定を使う
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        match format_from_path(path)?.mime_type {
+            "application/zip" => Some(Self::Zip),
+            "application/x-tar" => Some(Self::Tar),
+            "application/gzip" => Some(Self::TarGz),
+            "application/x-bzip2" => Some(Self::TarBz2),
+            "application/x-xz" => Some(Self::TarXz),
+            "application/x-7z-compressed" => Some(Self::SevenZip),
+            "application/vnd.rar" => Some(Self::Rar),
+            _ => None,
+        }
+    }
+}
+
+/// アーカイブ内のエントリ情報
+#[derive(Debug, Clone)]
+pub(crate) struct ArchiveEntry {
+    /// アーカイブ内のパス（区切り文字は `/`）
+    pub name: String,
+    pub is_dir: bool,
+}
+
+/// 圧縮されずに格納されたエントリの実ファイル上の位置
+/// 展開せずに任意の範囲を読めるため、大きな動画のシークに使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredEntry {
+    /// エントリのデータを含む実ファイル（元のアーカイブまたはスプールファイル）
+    pub path: PathBuf,
+    /// データの開始位置
+    pub offset: u64,
+    pub size: u64,
+    /// `path` がスプールファイルの場合、読み終わるまで削除されないよう保持する参照
+    pub spool: Option<Arc<SpoolGuard>>,
+}
+
+/// アーカイブを開く際の設定
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions {
+    /// アーカイブのパス -> ファイル名の文字コード（未指定なら自動判定）
+    pub name_encodings: HashMap<String, NameEncoding>,
+    /// アーカイブのパス -> パスワード（暗号化されたアーカイブ用）
+    pub passwords: HashMap<String, String>,
+}
+
+impl ArchiveOptions {
+    /// 指定したアーカイブに対するファイル名の文字コード指定
+    fn name_encoding(&self, archive_path: &str) -> Option<NameEncoding> {
+        self.name_encodings.get(archive_path).copied()
+    }
+
+    /// 指定したアーカイブのパスワード
+    fn password(&self, archive_path: &str) -> Option<String> {
+        self.passwords.get(archive_path).cloned()
+    }
+}
+
+/// パスワード関連のエラー
+/// フロントエンドでパスワード入力を促すため、他の読み込みエラーと区別して扱う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ArchivePasswordError {
+    /// パスワードが未指定（値は対象アーカイブのパス）
+    Required(String),
+    /// パスワードが誤っている（値は対象アーカイブのパス）
+    Invalid(String),
+}
+
+/// パスワード未指定エラーのメッセージの接頭辞
+pub(crate) const PASSWORD_REQUIRED_ERROR: &str = "password required";
+/// パスワード誤りエラーのメッセージの接頭辞
+pub(crate) const INVALID_PASSWORD_ERROR: &str = "invalid password";
+
+impl fmt::Display for ArchivePasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Required(path) => write!(f, "{}: {}", PASSWORD_REQUIRED_ERROR, path),
+            Self::Invalid(path) => write!(f, "{}: {}", INVALID_PASSWORD_ERROR, path),
+        }
+    }
+}
+
+impl std::error::Error for ArchivePasswordError {}
+
+/// エラーの原因にパスワード関連のエラーが含まれていれば取り出す
+pub(crate) fn find_password_error(e: &anyhow::Error) -> Option<&ArchivePasswordError> {
+    e.chain()
+        .find_map(|cause| cause.downcast_ref::<ArchivePasswordError>())
+}
+
+/// 形式非依存のアーカイブ読み込みインターフェース
+pub(crate) trait ArchiveReader: Send {
+    /// アーカイブ内の全エントリ（格納順）
+    fn entries(&self) -> &[ArchiveEntry];
+
+    /// 指定したエントリを展開して読み込む
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>>;
+
+    /// 指定したエントリを展開して `output` に書き出す
+    /// ストリームで展開できる形式は、エントリ全体をメモリに載せないよう上書きする
+    fn write_entry(&mut self, name: &str, output: &mut dyn Write) -> Result<()> {
+        let buf = self.read_entry(name)?;
+        output.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// 指定したエントリが圧縮・暗号化されずに格納されていれば、実ファイル上の位置を返す
+    /// 位置を特定できない形式や圧縮されたエントリは None
+    fn stored_entry(&mut self, _name: &str) -> Result<Option<StoredEntry>> {
+        Ok(None)
+    }
+
+    /// 1 つのエントリを読むためにも、先行するエントリの展開が必要なソリッドアーカイブか
+    fn is_solid(&self) -> bool {
+        false
+    }
+}
+
+/// アーカイブを開く
+/// `!/` で連結されたパスの場合は、内側のアーカイブを順にスプールへ展開して開く
+pub(crate) fn open_archive(path: &str, options: &ArchiveOptions) -> Result<Box<dyn ArchiveReader>> {
+    let mut segments = path.split(NESTED_ARCHIVE_SEPARATOR);
+    let root = segments.next().unwrap_or_default();
+    let mut archive = open_archive_file(root, root, archive_format(root)?, options)?;
+    let mut real_path = root.to_string();
+    let mut archive_path = root.to_string();
+    for name in segments {
+        archive_path = join_nested_archive_path(&archive_path, name);
+        let format = archive_format(name)?;
+        let spool = get_or_create_spool(&real_path, &format!("nested|{}", name), |output| {
+            archive.write_entry(name, output)
+        })
+        .with_context(|| format!("failed to extract nested archive: {}", name))?;
+        real_path = spool.path().to_string_lossy().into_owned();
+        archive = Box::new(NestedArchive {
+            inner: open_archive_file(&real_path, &archive_path, format, options)?,
+            spool: Arc::new(spool),
+        });
+    }
+    Ok(archive)
+}
+
+/// スプールに展開した内側のアーカイブ
+/// 開いている間はスプールが削除されないよう参照を保持する
+struct NestedArchive {
+    inner: Box<dyn ArchiveReader>,
+    spool: Arc<SpoolGuard>,
+}
+
+impl ArchiveReader for NestedArchive {
+    fn entries(&self) -> &[ArchiveEntry] {
+        self.inner.entries()
+    }
+
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        self.inner.read_entry(name)
+    }
+
+    fn write_entry(&mut self, name: &str, output: &mut dyn Write) -> Result<()> {
+        self.inner.write_entry(name, output)
+    }
+
+    fn stored_entry(&mut self, name: &str) -> Result<Option<StoredEntry>> {
+        Ok(self.inner.stored_entry(name)?.map(|stored| StoredEntry {
+            spool: stored.spool.or_else(|| Some(self.spool.clone())),
+            ..stored
+        }))
+    }
+
+    fn is_solid(&self) -> bool {
+        self.inner.is_solid()
+    }
+}
+
+/// エントリの実ファイル上の位置を返す。圧縮されたエントリはスプールへ展開し、その位置を返す
+/// 動画の範囲リクエストのたびにエントリ全体を展開し直さないよう、展開結果を使い回す
+pub(crate) fn stored_or_spooled_entry(
+    archive: &mut dyn ArchiveReader,
+    archive_path: &str,
+    name: &str,
+) -> Result<StoredEntry> {
+    if let Some(stored) = archive.stored_entry(name)? {
+        return Ok(stored);
+    }
+    let tag = format!("entry|{}", join_nested_archive_path(archive_path, name));
+    let spool = get_or_create_spool(root_archive_path(archive_path), &tag, |output| {
+        archive.write_entry(name, output)
+    })
+    .with_context(|| format!("failed to extract entry: {}", name))?;
+    let size = std::fs::metadata(spool.path())?.len();
+    Ok(StoredEntry {
+        path: spool.path().to_path_buf(),
+        offset: 0,
+        size,
+        spool: Some(Arc::new(spool)),
+    })
+}
+
+fn archive_format(path: &str) -> Result<ArchiveFormat> {
+    ArchiveFormat::from_path(path).ok_or_else(|| anyhow!("unsupported archive: {}", path))
+}
+
+/// 実ファイルとして存在するアーカイブを指定した形式で開く
+/// `archive_path` は設定の参照とエラー表示に使う `!/` 連結のパス
+/// 逐次読み込みしかできない形式は、ここで初回のインデックス構築まで行う
+fn open_archive_file(
+    path: &str,
+    archive_path: &str,
+    format: ArchiveFormat,
+    options: &ArchiveOptions,
+) -> Result<Box<dyn ArchiveReader>> {
+    let reader: Box<dyn ArchiveReader> = match format {
+        ArchiveFormat::Zip => Box::new(ZipReader::open(
+            path,
+            archive_path,
+            options.name_encoding(archive_path),
+            options.password(archive_path),
+        )?),
+        ArchiveFormat::Tar => Box::new(TarReader::open(path, TarCompression::None)?),
+        ArchiveFormat::TarGz => Box::new(TarReader::open(path, TarCompression::Gzip)?),
+        ArchiveFormat::TarBz2 => Box::new(TarReader::open(path, TarCompression::Bzip2)?),
+        ArchiveFormat::TarXz => Box::new(TarReader::open(path, TarCompression::Xz)?),
+        ArchiveFormat::SevenZip => Box::new(SevenZipReader::open(path)?),
+        ArchiveFormat::Rar => Box::new(RarReader::open(path)?),
+    };
+    Ok(reader)
+}
+
+/// アーカイブ内のエントリパスを `!/` で連結する
+pub(crate) fn join_nested_archive_path(archive_path: &str, name: &str) -> String {
+    format!("{}{}{}", archive_path, NESTED_ARCHIVE_SEPARATOR, name)
+}
+
+/// `!/` 連結のパスから、実ファイルとして存在する最も外側のアーカイブのパスを取り出す
+pub(crate) fn root_archive_path(path: &str) -> &str {
+    path.split(NESTED_ARCHIVE_SEPARATOR)
+        .next()
+        .unwrap_or_default()
+}
+
+/// アーカイブ内のファイル名一覧を取得する（ディレクトリエントリは除く）
+pub(crate) fn list_archive_file_names(archive: &dyn ArchiveReader) -> Vec<String> {
+    archive
+        .entries()
+        .iter()
+        .filter(|e| !e.is_dir)
+        .map(|e| e.name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_format_from_path() {
+        assert_eq!(
+            ArchiveFormat::from_path("a/b.ZIP"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::from_path("b.tar"), Some(ArchiveFormat::Tar));
+        assert_eq!(
+            ArchiveFormat::from_path("b.tar.gz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path("b.tgz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path("b.tar.bz2"),
+            Some(ArchiveFormat::TarBz2)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path("b.tbz"),
+            Some(ArchiveFormat::TarBz2)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path("b.txz"),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_path("b.7z"),
+            Some(ArchiveFormat::SevenZip)
+        );
+        assert_eq!(ArchiveFormat::from_path("b.rar"), Some(ArchiveFormat::Rar));
+        assert_eq!(ArchiveFormat::from_path("b.cbz"), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::from_path("b.cbr"), Some(ArchiveFormat::Rar));
+        assert_eq!(
+            ArchiveFormat::from_path("b.cb7"),
+            Some(ArchiveFormat::SevenZip)
+        );
+        assert_eq!(ArchiveFormat::from_path("b.cbt"), Some(ArchiveFormat::Tar));
+        assert_eq!(ArchiveFormat::from_path("b.png"), None);
+        assert_eq!(ArchiveFormat::from_path("image.png.gz"), None);
+        assert_eq!(ArchiveFormat::from_path("b.bz2"), None);
+        assert_eq!(ArchiveFormat::from_path("b.xz"), None);
+    }
+
+    #[test]
+    fn test_join_nested_archive_path() {
+        let path = join_nested_archive_path("outer.zip", "vol1.zip");
+        assert_eq!(path, "outer.zip!/vol1.zip");
+        assert_eq!(
+            path.split(NESTED_ARCHIVE_SEPARATOR).collect::<Vec<_>>(),
+            vec!["outer.zip", "vol1.zip"]
+        );
+    }
+}
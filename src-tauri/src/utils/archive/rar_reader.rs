@@ -0,0 +1,166 @@
+//! RAR 形式の読み込み (RAR4 / RAR5)
+//!
+//! unrar はエントリを先頭から順にしか処理できないため、非ソリッドでは目的のエントリまで
+//! ヘッダを読み飛ばして展開する。ソリッドでは読み飛ばしにも展開が必要になるので、
+//! 初回に全ファイルをスプールし、以降はオフセットを計算してシークで読み込む
+//!
+//! unrar はエントリをメモリに読み込むか、ファイルに展開するかしかできないため、
+//! スプールなどへ書き出すときは一時ファイルに展開してからコピーし、エントリ全体をメモリに載せない
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use unrar::{CursorBeforeFile, CursorBeforeHeader, OpenArchive, Process};
+
+use super::spool::{create_temp_path, get_or_create_spool};
+use super::{ArchiveEntry, ArchiveReader, StoredEntry};
+
+pub(super) struct RarReader {
+    path: String,
+    entries: Vec<ArchiveEntry>,
+    /// エントリ名 -> (スプール内のオフセット, サイズ)
+    index: HashMap<String, (u64, u64)>,
+    is_solid: bool,
+}
+
+impl RarReader {
+    pub(super) fn open(path: &str) -> Result<Self> {
+        let archive = unrar::Archive::new(path)
+            .open_for_listing()
+            .context("failed to read rar")?;
+        let is_solid = archive.is_solid();
+
+        let mut entries = vec![];
+        let mut index = HashMap::new();
+        let mut offset = 0u64;
+        for header in archive {
+            let header = header.context("failed to read rar entry")?;
+            let name = entry_name(&header);
+            let is_dir = header.is_directory();
+            if !is_dir {
+                index.insert(name.clone(), (offset, header.unpacked_size));
+                offset += header.unpacked_size;
+            }
+            entries.push(ArchiveEntry { name, is_dir });
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            entries,
+            index,
+            is_solid,
+        })
+    }
+
+    fn locate(&self, name: &str) -> Result<(u64, u64)> {
+        self.index
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("file not found in rar: {}", name))
+    }
+
+    /// 目的のエントリまでヘッダを読み飛ばし、`extract` で展開する
+    fn process_entry<T>(
+        &self,
+        name: &str,
+        extract: impl FnOnce(OpenArchive<Process, CursorBeforeFile>) -> Result<T>,
+    ) -> Result<T> {
+        let mut archive = unrar::Archive::new(&self.path)
+            .open_for_processing()
+            .context("failed to read rar")?;
+        while let Some(header) = archive.read_header()? {
+            if !header.entry().is_directory() && entry_name(header.entry()) == name {
+                return extract(header);
+            }
+            archive = header.skip()?;
+        }
+        Err(anyhow!("file not found in rar: {}", name))
+    }
+
+    /// 全ファイルを格納順にスプールし、スプール内の目的のエントリの位置を返す
+    fn solid_entry(&self, offset: u64, size: u64) -> Result<StoredEntry> {
+        let spool = get_or_create_spool(&self.path, "rar-solid", |output| {
+            let mut archive = unrar::Archive::new(&self.path).open_for_processing()?;
+            while let Some(header) = archive.read_header()? {
+                archive = if header.entry().is_directory() {
+                    header.skip()?
+                } else {
+                    extract_to_writer(header, output)?
+                };
+            }
+            Ok(())
+        })
+        .context("failed to decompress rar")?;
+
+        Ok(StoredEntry {
+            path: spool.path().to_path_buf(),
+            offset,
+            size,
+            spool: Some(Arc::new(spool)),
+        })
+    }
+}
+
+impl ArchiveReader for RarReader {
+    fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        if self.is_solid {
+            let (_, size) = self.locate(name)?;
+            let mut buf = Vec::with_capacity(size as usize);
+            self.write_entry(name, &mut buf)?;
+            return Ok(buf);
+        }
+        self.process_entry(name, |header| {
+            let (data, _) = header.read().context("failed to read file")?;
+            Ok(data)
+        })
+    }
+
+    fn write_entry(&mut self, name: &str, output: &mut dyn Write) -> Result<()> {
+        let (offset, size) = self.locate(name)?;
+        if self.is_solid {
+            let entry = self.solid_entry(offset, size)?;
+            let mut file = File::open(&entry.path)?;
+            file.seek(SeekFrom::Start(entry.offset))?;
+            std::io::copy(&mut file.take(entry.size), output)?;
+            Ok(())
+        } else {
+            self.process_entry(name, |header| extract_to_writer(header, output).map(|_| ()))
+        }
+    }
+
+    /// ソリッドのアーカイブはスプール内の位置を返す
+    /// 非ソリッドのエントリは圧縮されているため None
+    fn stored_entry(&mut self, name: &str) -> Result<Option<StoredEntry>> {
+        let (offset, size) = self.locate(name)?;
+        if !self.is_solid {
+            return Ok(None);
+        }
+        self.solid_entry(offset, size).map(Some)
+    }
+
+    fn is_solid(&self) -> bool {
+        self.is_solid
+    }
+}
+
+/// 現在のエントリを一時ファイルに展開してから `output` にコピーし、次のエントリへ進む
+fn extract_to_writer(
+    header: OpenArchive<Process, CursorBeforeFile>,
+    output: &mut dyn Write,
+) -> Result<OpenArchive<Process, CursorBeforeHeader>> {
+    let temp = create_temp_path()?;
+    let next = header.extract_to(&temp).context("failed to read file")?;
+    std::io::copy(&mut File::open(&temp)?, output)?;
+    Ok(next)
+}
+
+/// アーカイブ内のパスを `/` 区切りの文字列にする
+fn entry_name(header: &unrar::FileHeader) -> String {
+    header.filename.to_string_lossy().replace('\\', "/")
+}
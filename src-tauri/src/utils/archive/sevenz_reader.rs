@@ -0,0 +1,178 @@
+//! 7z 形式の読み込み (LZMA / LZMA2、ソリッド・非ソリッド)
+//!
+//! 7z はブロック（フォルダ）単位で圧縮されている。非ソリッドではブロックに 1 ファイルなので
+//! 目的のブロックだけを展開すればよいが、ソリッドでは 1 ブロックに複数ファイルが連結されており
+//! 途中から展開できない。そのためソリッドブロックは初回にブロック全体をスプールし、
+//! 以降はブロック内のオフセットを計算してシークで読み込む
+//! どちらもエントリはストリームで書き出し、エントリ全体をメモリに載せない
+
+use anyhow::{anyhow, Context, Result};
+use sevenz_rust::{Archive, BlockDecoder};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use super::spool::get_or_create_spool;
+use super::{ArchiveEntry, ArchiveReader, StoredEntry};
+
+pub(super) struct SevenZipReader {
+    path: String,
+    source: File,
+    archive: Archive,
+    entries: Vec<ArchiveEntry>,
+    /// エントリ名 -> archive.files のインデックス
+    index: HashMap<String, usize>,
+}
+
+impl SevenZipReader {
+    pub(super) fn open(path: &str) -> Result<Self> {
+        let mut source = File::open(path).context("failed to open 7z")?;
+        let len = source.metadata()?.len();
+        let archive = Archive::read(&mut source, len, &[]).context("failed to read 7z")?;
+
+        let mut entries = vec![];
+        let mut index = HashMap::new();
+        for (i, file) in archive.files.iter().enumerate() {
+            if file.is_anti_item() {
+                continue;
+            }
+            let name = file.name().replace('\\', "/");
+            if !file.is_directory() {
+                index.insert(name.clone(), i);
+            }
+            entries.push(ArchiveEntry {
+                name,
+                is_dir: file.is_directory(),
+            });
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            source,
+            archive,
+            entries,
+            index,
+        })
+    }
+
+    /// エントリ名から archive.files のインデックスと、データを含むブロックのインデックスを返す
+    /// データを持たない空ファイルのブロックは None
+    fn locate(&self, name: &str) -> Result<(usize, Option<usize>)> {
+        let file_index = *self
+            .index
+            .get(name)
+            .ok_or_else(|| anyhow!("file not found in 7z: {}", name))?;
+        Ok((
+            file_index,
+            self.archive.stream_map.file_folder_index[file_index],
+        ))
+    }
+
+    fn is_solid_block(&self, folder_index: usize) -> bool {
+        self.archive.folders[folder_index].num_unpack_sub_streams > 1
+    }
+
+    /// 非ソリッドブロックから目的のファイルだけを展開して `output` に書き出す
+    fn write_from_block(
+        &mut self,
+        folder_index: usize,
+        file_index: usize,
+        output: &mut dyn Write,
+    ) -> Result<()> {
+        let target = &self.archive.files[file_index];
+        BlockDecoder::new(folder_index, &self.archive, &[], &mut self.source).for_each_entries(
+            &mut |entry, reader| {
+                if std::ptr::eq(entry, target) {
+                    std::io::copy(reader, output)?;
+                    Ok(false)
+                } else {
+                    // ブロック内の後続ファイルを正しく読むため、対象外のデータも読み捨てる
+                    std::io::copy(reader, &mut std::io::sink())?;
+                    Ok(true)
+                }
+            },
+        )?;
+        Ok(())
+    }
+
+    /// ソリッドブロックをスプールし、スプール内の目的のファイルの位置を返す
+    fn solid_entry(&mut self, folder_index: usize, file_index: usize) -> Result<StoredEntry> {
+        let archive = &self.archive;
+        let source = &mut self.source;
+        let spool = get_or_create_spool(
+            &self.path,
+            &format!("7z-block-{}", folder_index),
+            |output| {
+                BlockDecoder::new(folder_index, archive, &[], source).for_each_entries(
+                    &mut |_, reader| {
+                        std::io::copy(reader, output)?;
+                        Ok(true)
+                    },
+                )?;
+                Ok(())
+            },
+        )
+        .context("failed to decompress 7z block")?;
+
+        // ブロック内のオフセット = 先行するファイルのサイズの合計
+        let first = archive.stream_map.folder_first_file_index[folder_index];
+        let offset: u64 = archive.files[first..file_index]
+            .iter()
+            .filter(|f| f.has_stream())
+            .map(|f| f.size())
+            .sum();
+        Ok(StoredEntry {
+            path: spool.path().to_path_buf(),
+            offset,
+            size: archive.files[file_index].size(),
+            spool: Some(Arc::new(spool)),
+        })
+    }
+}
+
+impl ArchiveReader for SevenZipReader {
+    fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        let (file_index, _) = self.locate(name)?;
+        let mut buf = Vec::with_capacity(self.archive.files[file_index].size() as usize);
+        self.write_entry(name, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_entry(&mut self, name: &str, output: &mut dyn Write) -> Result<()> {
+        let (file_index, folder_index) = self.locate(name)?;
+        let Some(folder_index) = folder_index else {
+            // データを持たない空ファイル
+            return Ok(());
+        };
+        if self.is_solid_block(folder_index) {
+            let entry = self.solid_entry(folder_index, file_index)?;
+            let mut file = File::open(&entry.path)?;
+            file.seek(SeekFrom::Start(entry.offset))?;
+            std::io::copy(&mut file.take(entry.size), output)?;
+            Ok(())
+        } else {
+            self.write_from_block(folder_index, file_index, output)
+        }
+    }
+
+    /// ソリッドブロックのファイルはスプール内の位置を返す
+    /// 非ソリッドブロックのファイルは圧縮されているため None
+    fn stored_entry(&mut self, name: &str) -> Result<Option<StoredEntry>> {
+        let (file_index, folder_index) = self.locate(name)?;
+        match folder_index {
+            Some(folder_index) if self.is_solid_block(folder_index) => {
+                self.solid_entry(folder_index, file_index).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn is_solid(&self) -> bool {
+        (0..self.archive.folders.len()).any(|i| self.is_solid_block(i))
+    }
+}
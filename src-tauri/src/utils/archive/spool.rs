@@ -0,0 +1,160 @@
+//! 展開済みデータのスプール（一時ファイル）管理
+//!
+//! 圧縮 TAR やソリッド 7z のように途中から展開できない形式は、一度展開した結果を
+//! 一時ディレクトリに保存しておき、以降はシークで読み込む
+//!
+//! スプールは合計サイズの上限を超えると古いものから削除する。使用中のスプールは
+//! `SpoolGuard` が参照カウントで保持し、削除の対象から外す
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::{NamedTempFile, TempPath};
+
+/// 保持するスプールファイルの合計サイズの上限
+const MAX_SPOOL_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// スプールファイルの拡張子
+const SPOOL_EXTENSION: &str = "spool";
+
+/// 使用中のスプールファイル -> 参照数
+static SPOOLS_IN_USE: Mutex<BTreeMap<PathBuf, usize>> = Mutex::new(BTreeMap::new());
+
+/// 使用中のスプールファイルの参照
+/// 保持している間はスプールが削除されない
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SpoolGuard {
+    path: PathBuf,
+}
+
+impl SpoolGuard {
+    fn acquire(path: PathBuf) -> Self {
+        if let Ok(mut in_use) = SPOOLS_IN_USE.lock() {
+            *in_use.entry(path.clone()).or_default() += 1;
+        }
+        Self { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SpoolGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_use) = SPOOLS_IN_USE.lock() {
+            if let Some(count) = in_use.get_mut(&self.path) {
+                *count -= 1;
+                if *count == 0 {
+                    in_use.remove(&self.path);
+                }
+            }
+        }
+    }
+}
+
+/// スプールファイルの保存先ディレクトリ
+fn spool_dir() -> PathBuf {
+    std::env::temp_dir()
+        .join("simple-image-viewer")
+        .join("archive-spool")
+}
+
+/// スプールのディレクトリに一時ファイルのパスを作る（ドロップすると削除される）
+/// ファイルにしか展開できない形式で、展開先として使う
+pub(super) fn create_temp_path() -> Result<TempPath> {
+    let dir = spool_dir();
+    std::fs::create_dir_all(&dir)?;
+    Ok(NamedTempFile::new_in(&dir)?.into_temp_path())
+}
+
+/// アーカイブのパス・サイズ・更新日時と用途タグからスプールのキーを作る
+/// アーカイブが更新されるとキーが変わり、古いスプールは使われなくなる
+pub(super) fn spool_key(path: &str, tag: &str) -> Result<String> {
+    let metadata = std::fs::metadata(path).context("failed to open archive")?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    Ok(format!(
+        "{:x}",
+        Sha256::digest(format!("{}|{}|{}|{}", path, metadata.len(), modified, tag))
+    ))
+}
+
+/// スプールファイルの参照を返す。まだ存在しなければ `write` で作成する
+/// 削除されないよう、存在を確認する前に参照を取得する
+pub(super) fn get_or_create_spool<F>(path: &str, tag: &str, write: F) -> Result<SpoolGuard>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let key = spool_key(path, tag)?;
+    let dir = spool_dir();
+    std::fs::create_dir_all(&dir)?;
+    let guard = SpoolGuard::acquire(dir.join(format!("{}.{}", key, SPOOL_EXTENSION)));
+    if guard.path().exists() {
+        // 最近使ったスプールを残すよう、更新日時を使用日時として更新する
+        let _ = File::options()
+            .write(true)
+            .open(guard.path())
+            .and_then(|f| f.set_modified(SystemTime::now()));
+        return Ok(guard);
+    }
+
+    // 途中で失敗した場合に不完全なスプールを再利用しないよう、一時ファイルに書いてからリネームする
+    // 同じスプールを同時に作る場合に互いの書きかけを壊さないよう、一時ファイルは書き込みごとに分ける
+    // （失敗した場合、一時ファイルはドロップで削除される）
+    let mut part = NamedTempFile::new_in(&dir)?;
+    {
+        let mut output = BufWriter::new(part.as_file_mut());
+        write(&mut output)?;
+        output.flush()?;
+    }
+    if let Err(e) = part.persist(guard.path()) {
+        // 先に書き終えた側のスプールがあればそれを使う（Windows では使用中のファイルを置き換えられない）
+        if !guard.path().exists() {
+            return Err(e.error.into());
+        }
+    }
+
+    prune_spool_dir(&dir);
+    Ok(guard)
+}
+
+/// 使われていない古いスプールファイルを削除して、合計サイズを MAX_SPOOL_BYTES 以内に保つ
+/// 使用中のスプールは上限を超えていても削除しない
+fn prune_spool_dir(dir: &Path) {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut spools: Vec<_> = read_dir
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(SPOOL_EXTENSION))
+        .filter_map(|p| {
+            let metadata = std::fs::metadata(&p).ok()?;
+            Some((p, metadata.len(), metadata.modified().ok()?))
+        })
+        .collect();
+    spools.sort_by_key(|s| std::cmp::Reverse(s.2));
+
+    // 参照の取得と削除が入れ違わないよう、削除が終わるまで使用中の一覧をロックしておく
+    let Ok(in_use) = SPOOLS_IN_USE.lock() else {
+        return;
+    };
+    let mut total = 0u64;
+    for (path, len, _) in spools {
+        total += len;
+        if total > MAX_SPOOL_BYTES && !in_use.contains_key(&path) {
+            let _ = std::fs::remove_file(path);
+            total -= len;
+        }
+    }
+}
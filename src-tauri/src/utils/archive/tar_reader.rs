@@ -0,0 +1,159 @@
+//! TAR / TAR.GZ / TAR.BZ2 / TAR.XZ 形式の読み込み
+//!
+//! TAR は先頭から順にしか読めないため、初回オープン時に全エントリを走査して
+//! (データ開始位置, サイズ) のインデックスを作り、以降はシークで直接読み込む。
+//! 圧縮 TAR はシークできないので、展開済み TAR を一時ディレクトリにスプールしてから索引化する
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::spool::{get_or_create_spool, SpoolGuard};
+use super::{ArchiveEntry, ArchiveReader, StoredEntry};
+
+/// エントリ名 -> (データ開始オフセット, サイズ)
+type TarIndex = HashMap<String, (u64, u64)>;
+
+/// TAR の外側の圧縮形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TarCompression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+}
+
+pub(super) struct TarReader {
+    /// 非圧縮 TAR（元ファイルまたはスプールファイル）
+    file: File,
+    tar_path: PathBuf,
+    /// 圧縮 TAR の場合のスプールの参照（開いている間は削除されないよう保持する）
+    spool: Option<Arc<SpoolGuard>>,
+    entries: Vec<ArchiveEntry>,
+    index: TarIndex,
+}
+
+impl TarReader {
+    pub(super) fn open(path: &str, compression: TarCompression) -> Result<Self> {
+        let spool = match compression {
+            TarCompression::None => None,
+            _ => Some(Arc::new(spool_decompressed(path, compression)?)),
+        };
+        let tar_path = spool
+            .as_ref()
+            .map_or_else(|| PathBuf::from(path), |spool| spool.path().to_path_buf());
+        let file = File::open(&tar_path).context("failed to open tar")?;
+        let (entries, index) = build_index(&file)?;
+        Ok(Self {
+            file,
+            tar_path,
+            spool,
+            entries,
+            index,
+        })
+    }
+
+    /// エントリのデータ開始位置へシークし、サイズを返す
+    fn seek_entry(&mut self, name: &str) -> Result<u64> {
+        let (offset, size) = *self
+            .index
+            .get(name)
+            .ok_or_else(|| anyhow!("file not found in tar: {}", name))?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        Ok(size)
+    }
+}
+
+impl ArchiveReader for TarReader {
+    fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        let size = self.seek_entry(name)?;
+        let mut buf = Vec::with_capacity(size as usize);
+        (&self.file)
+            .take(size)
+            .read_to_end(&mut buf)
+            .context("failed to read file")?;
+        Ok(buf)
+    }
+
+    fn write_entry(&mut self, name: &str, output: &mut dyn Write) -> Result<()> {
+        let size = self.seek_entry(name)?;
+        std::io::copy(&mut (&self.file).take(size), output).context("failed to read file")?;
+        Ok(())
+    }
+
+    fn stored_entry(&mut self, name: &str) -> Result<Option<StoredEntry>> {
+        let (offset, size) = *self
+            .index
+            .get(name)
+            .ok_or_else(|| anyhow!("file not found in tar: {}", name))?;
+        Ok(Some(StoredEntry {
+            path: self.tar_path.clone(),
+            offset,
+            size,
+            spool: self.spool.clone(),
+        }))
+    }
+}
+
+/// 非圧縮 TAR を先頭から走査してインデックスを構築する
+/// エントリのデータ部分は読み飛ばされるため、ヘッダ分の I/O で済む
+fn build_index(file: &File) -> Result<(Vec<ArchiveEntry>, TarIndex)> {
+    let mut archive = tar::Archive::new(BufReader::new(file));
+    let mut entries = vec![];
+    let mut index = HashMap::new();
+    for entry in archive.entries_with_seek().context("failed to read tar")? {
+        let entry = entry.context("failed to read tar entry")?;
+        let entry_type = entry.header().entry_type();
+        let is_dir = entry_type.is_dir();
+        if !is_dir && !entry_type.is_file() {
+            // シンボリックリンク等は扱わない
+            continue;
+        }
+        let name = entry
+            .path()?
+            .to_string_lossy()
+            .replace('\\', "/")
+            .trim_start_matches("./")
+            .to_string();
+        if name.is_empty() {
+            continue;
+        }
+        let size = entry.size();
+        if !is_dir {
+            index.insert(name.clone(), (entry.raw_file_position(), size));
+        }
+        entries.push(ArchiveEntry { name, is_dir });
+    }
+    Ok((entries, index))
+}
+
+/// 圧縮 TAR を展開してスプールし、その参照を返す
+fn spool_decompressed(path: &str, compression: TarCompression) -> Result<SpoolGuard> {
+    get_or_create_spool(path, "tar", |output| {
+        let input = BufReader::new(File::open(path)?);
+        match compression {
+            TarCompression::None => unreachable!("plain tar is not spooled"),
+            TarCompression::Gzip => {
+                std::io::copy(&mut flate2::read::MultiGzDecoder::new(input), output)?;
+            }
+            TarCompression::Bzip2 => {
+                std::io::copy(&mut bzip2::read::MultiBzDecoder::new(input), output)?;
+            }
+            TarCompression::Xz => {
+                let mut input = input;
+                let mut output = output;
+                lzma_rs::xz_decompress(&mut input, &mut output)
+                    .map_err(|e| anyhow!("failed to decompress xz: {:?}", e))?;
+            }
+        }
+        Ok(())
+    })
+    .context("failed to decompress tar")
+}
@@ -0,0 +1,225 @@
+//! ZIP 形式の読み込み
+//!
+//! UTF-8 フラグのあるファイル名は UTF-8 として、フラグのないファイル名は文字コードを判定して表示用の名前に変換する。
+//! 変換後の名前では元のエントリを名前検索できないため、名前からエントリ番号への索引で読み込む
+//! 暗号化されたエントリ (ZipCrypto / AES) は、指定されたパスワードで復号して読み込む
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use zip::result::ZipError;
+use zip::CompressionMethod;
+
+use super::{ArchiveEntry, ArchivePasswordError, ArchiveReader, NameEncoding, StoredEntry};
+
+pub(super) struct ZipReader {
+    zip: zip::ZipArchive<BufReader<File>>,
+    /// ZIP の実ファイル（元ファイルまたはスプールファイル）
+    path: PathBuf,
+    /// エラー表示用のアーカイブのパス
+    archive_path: String,
+    password: Option<String>,
+    entries: Vec<ArchiveEntry>,
+    /// 表示用のエントリ名 -> ZIP 内のエントリ番号
+    index: HashMap<String, usize>,
+}
+
+impl ZipReader {
+    /// ZIP を開き、セントラルディレクトリからエントリ一覧を作成する
+    /// `name_encoding` が指定されていればファイル名をその文字コードで解釈し、なければ自動判定する
+    pub(super) fn open(
+        path: &str,
+        archive_path: &str,
+        name_encoding: Option<NameEncoding>,
+        password: Option<String>,
+    ) -> Result<Self> {
+        let file = File::open(path).context("failed to open zip")?;
+        let mut zip = zip::ZipArchive::new(BufReader::new(file)).context("failed to read zip")?;
+        let mut raw_names = Vec::with_capacity(zip.len());
+        let mut header_starts = Vec::with_capacity(zip.len());
+        for i in 0..zip.len() {
+            let file = zip.by_index_raw(i)?;
+            raw_names.push(file.name_raw().to_vec());
+            header_starts.push(file.central_header_start());
+        }
+        let raw_names: Vec<_> = raw_names
+            .into_iter()
+            .zip(read_utf8_flags(path, &header_starts)?)
+            .collect();
+        // フラグのない名前の文字コードは 1 エントリずつではなくまとめて判定し、名前の混在を防ぐ
+        let name_encoding = name_encoding.unwrap_or_else(|| {
+            let unflagged: Vec<u8> = raw_names
+                .iter()
+                .filter(|(_, is_utf8)| !is_utf8)
+                .flat_map(|(raw_name, _)| raw_name.iter().copied())
+                .collect();
+            NameEncoding::detect(&unflagged)
+        });
+
+        let mut entries = Vec::with_capacity(zip.len());
+        let mut index = HashMap::new();
+        for (i, (raw_name, is_utf8)) in raw_names.iter().enumerate() {
+            let encoding = match is_utf8 {
+                true => NameEncoding::Utf8,
+                false => name_encoding,
+            };
+            let name = encoding.decode(raw_name).replace('\\', "/");
+            let is_dir = name.ends_with('/');
+            if !is_dir {
+                index.insert(name.clone(), i);
+            }
+            entries.push(ArchiveEntry { name, is_dir });
+        }
+        Ok(Self {
+            zip,
+            path: PathBuf::from(path),
+            archive_path: archive_path.to_string(),
+            password,
+            entries,
+            index,
+        })
+    }
+
+    fn entry_index(&self, name: &str) -> Result<usize> {
+        self.index
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("file not found in zip: {}", name))
+    }
+
+    /// エントリを開く。暗号化されている場合はパスワードで復号する
+    fn open_entry(&mut self, name: &str) -> Result<zip::read::ZipFile<'_>> {
+        let index = self.entry_index(name)?;
+        let result = match &self.password {
+            Some(password) => self.zip.by_index_decrypt(index, password.as_bytes()),
+            None => self.zip.by_index(index),
+        };
+        result.map_err(|e| match e {
+            ZipError::UnsupportedArchive(message) if message == ZipError::PASSWORD_REQUIRED => {
+                ArchivePasswordError::Required(self.archive_path.clone()).into()
+            }
+            ZipError::InvalidPassword => {
+                ArchivePasswordError::Invalid(self.archive_path.clone()).into()
+            }
+            e => anyhow::Error::from(e).context("failed to open file in zip"),
+        })
+    }
+}
+
+/// セントラルディレクトリの各エントリの位置から、UTF-8 フラグ（汎用フラグの bit 11）を読み取る
+/// zip クレートはフラグを公開していないため、セントラルディレクトリを直接読む
+fn read_utf8_flags(path: &str, header_starts: &[u64]) -> Result<Vec<bool>> {
+    let Some(&base) = header_starts.iter().min() else {
+        return Ok(vec![]);
+    };
+    let mut file = File::open(path).context("failed to open zip")?;
+    file.seek(SeekFrom::Start(base))?;
+    let mut directory = vec![];
+    file.read_to_end(&mut directory)?;
+    Ok(header_starts
+        .iter()
+        .map(|&start| {
+            // シグネチャ (4) + 作成したバージョン (2) + 必要なバージョン (2) + 汎用フラグ (2)
+            let offset = (start - base) as usize;
+            match directory.get(offset..offset + 10) {
+                Some(header) if header.starts_with(b"PK\x01\x02") => {
+                    u16::from_le_bytes([header[8], header[9]]) & (1 << 11) != 0
+                }
+                _ => false,
+            }
+        })
+        .collect())
+}
+
+impl ArchiveReader for ZipReader {
+    fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        let mut inner = self.open_entry(name)?;
+        let mut buf = Vec::with_capacity(inner.size() as usize);
+        inner.read_to_end(&mut buf).context("failed to read file")?;
+        Ok(buf)
+    }
+
+    fn write_entry(&mut self, name: &str, output: &mut dyn Write) -> Result<()> {
+        let mut inner = self.open_entry(name)?;
+        std::io::copy(&mut inner, output).context("failed to read file")?;
+        Ok(())
+    }
+
+    fn stored_entry(&mut self, name: &str) -> Result<Option<StoredEntry>> {
+        let index = self.entry_index(name)?;
+        let inner = self
+            .zip
+            .by_index_raw(index)
+            .context("failed to open file in zip")?;
+        // 暗号化されたエントリは暗号ヘッダ等の分だけ格納サイズが元のサイズより大きくなる
+        if inner.compression() != CompressionMethod::Stored
+            || inner.compressed_size() != inner.size()
+        {
+            return Ok(None);
+        }
+        Ok(Some(StoredEntry {
+            path: self.path.clone(),
+            offset: inner.data_start(),
+            size: inner.size(),
+            spool: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+
+    /// UTF-8 フラグのある `Ümlaut/002.png` と、フラグのない Shift_JIS の `画像/001.png` を含む ZIP
+    fn mixed_zip() -> PathBuf {
+        let mut buf = vec![];
+        {
+            let mut writer = zip::ZipWriter::new(std::io::Cursor::new(&mut buf));
+            let options = SimpleFileOptions::default();
+            // ASCII の名前にはフラグが立たないため、書き込んだ後に Shift_JIS のバイト列に置き換える
+            writer.start_file("XXXX/001.png", options).unwrap();
+            writer.write_all(b"a").unwrap();
+            writer.start_file("Ümlaut/002.png", options).unwrap();
+            writer.write_all(b"b").unwrap();
+            writer.finish().unwrap();
+        }
+        let (placeholder, sjis) = (b"XXXX/001.png", b"\x89\xe6\x91\x9c/001.png");
+        for i in 0..=buf.len() - placeholder.len() {
+            if &buf[i..i + placeholder.len()] == placeholder {
+                buf[i..i + placeholder.len()].copy_from_slice(sjis);
+            }
+        }
+        let path = std::env::temp_dir().join("simple-image-viewer-mixed-utf8-flag.zip");
+        std::fs::write(&path, buf).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_mixed_utf8_flag() {
+        let path = mixed_zip();
+        let path = path.to_str().unwrap();
+        let names = |encoding| {
+            let reader = ZipReader::open(path, path, encoding, None).unwrap();
+            reader
+                .entries()
+                .iter()
+                .map(|e| e.name.clone())
+                .collect::<Vec<_>>()
+        };
+        // フラグのない名前だけを判定した文字コードで解釈する
+        assert_eq!(names(None), vec!["画像/001.png", "Ümlaut/002.png"]);
+        // 指定した文字コードもフラグのない名前にだけ適用する
+        assert_eq!(
+            names(Some(NameEncoding::Cp437)),
+            vec!["ëµæ£/001.png", "Ümlaut/002.png"]
+        );
+    }
+}
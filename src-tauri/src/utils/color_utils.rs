@@ -0,0 +1,124 @@
+//! ICC プロファイルによる色の変換
+//!
+//! Display P3 や Adobe RGB のプロファイルを持つ画像の画素値をそのまま sRGB として扱うと、
+//! 色が薄く（彩度が低く）見える。バックエンドで作る縮小画像・変換画像・サムネイルなどは
+//! プロファイルを埋め込まずにエンコードするため、デコードした時点で埋め込みのプロファイルに従って
+//! sRGB に変換する（変換には `image` と同じ moxcms を使う）
+//!
+//! 設定で変換を無効にすると、元の画素値をそのまま使う。
+//! WebView がそのまま表示できる元の画像は、どちらの設定でも変換せずに渡す
+
+use anyhow::{anyhow, bail, Result};
+use image::DynamicImage;
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions, Xyzd};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 埋め込みの ICC プロファイルの扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorManagement {
+    /// プロファイルに従って sRGB に変換する
+    #[default]
+    ConvertToSrgb,
+    /// 変換せずに元の画素値のまま使う
+    PassThrough,
+}
+
+static PASS_THROUGH: AtomicBool = AtomicBool::new(false);
+
+/// ICC プロファイルの扱いを設定する
+pub(crate) fn set_color_management(mode: ColorManagement) {
+    PASS_THROUGH.store(mode == ColorManagement::PassThrough, Ordering::Relaxed);
+}
+
+/// 現在の ICC プロファイルの扱い
+pub(crate) fn color_management() -> ColorManagement {
+    match PASS_THROUGH.load(Ordering::Relaxed) {
+        true => ColorManagement::PassThrough,
+        false => ColorManagement::ConvertToSrgb,
+    }
+}
+
+/// 原色が sRGB と同じプロファイルか（sRGB のプロファイルを持つ画像の変換を省く）
+fn has_srgb_primaries(profile: &ColorProfile) -> bool {
+    let srgb = ColorProfile::new_srgb();
+    let is_near = |a: Xyzd, b: Xyzd| {
+        (a.x - b.x).abs() < 0.002 && (a.y - b.y).abs() < 0.002 && (a.z - b.z).abs() < 0.002
+    };
+    is_near(profile.red_colorant, srgb.red_colorant)
+        && is_near(profile.green_colorant, srgb.green_colorant)
+        && is_near(profile.blue_colorant, srgb.blue_colorant)
+}
+
+/// 埋め込みの ICC プロファイルに従って画像を sRGB に変換する
+/// 変換しない設定の場合や、sRGB のプロファイルの場合は何もしない
+/// RGB 以外（グレースケール・CMYK）のプロファイルは対象外
+pub(crate) fn convert_to_srgb(image: &mut DynamicImage, icc: &[u8]) -> Result<()> {
+    if color_management() == ColorManagement::PassThrough {
+        return Ok(());
+    }
+    let profile =
+        ColorProfile::new_from_slice(icc).map_err(|e| anyhow!("invalid icc profile: {}", e))?;
+    if profile.color_space != DataColorSpace::Rgb || has_srgb_primaries(&profile) {
+        return Ok(());
+    }
+    let srgb = ColorProfile::new_srgb();
+    let options = TransformOptions::default();
+    let to_error = |e| anyhow!("failed to convert color: {}", e);
+    match image {
+        DynamicImage::ImageRgb8(buf) => {
+            let transform = profile
+                .create_transform_8bit(Layout::Rgb, &srgb, Layout::Rgb, options)
+                .map_err(to_error)?;
+            let src = buf.as_raw().clone();
+            transform.transform(&src, buf).map_err(to_error)?;
+        }
+        DynamicImage::ImageRgba8(buf) => {
+            let transform = profile
+                .create_transform_8bit(Layout::Rgba, &srgb, Layout::Rgba, options)
+                .map_err(to_error)?;
+            let src = buf.as_raw().clone();
+            transform.transform(&src, buf).map_err(to_error)?;
+        }
+        DynamicImage::ImageRgb16(buf) => {
+            let transform = profile
+                .create_transform_16bit(Layout::Rgb, &srgb, Layout::Rgb, options)
+                .map_err(to_error)?;
+            let src = buf.as_raw().clone();
+            transform.transform(&src, buf).map_err(to_error)?;
+        }
+        DynamicImage::ImageRgba16(buf) => {
+            let transform = profile
+                .create_transform_16bit(Layout::Rgba, &srgb, Layout::Rgba, options)
+                .map_err(to_error)?;
+            let src = buf.as_raw().clone();
+            transform.transform(&src, buf).map_err(to_error)?;
+        }
+        _ => bail!("unsupported color type: {:?}", image.color()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_convert_to_srgb() {
+        let image = || DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([200, 100, 100])));
+
+        // Display P3 の色は sRGB では彩度の高い色になる
+        let p3 = ColorProfile::new_display_p3().encode().unwrap();
+        let mut converted = image();
+        convert_to_srgb(&mut converted, &p3).unwrap();
+        let Rgb([r, g, b]) = *converted.to_rgb8().get_pixel(0, 0);
+        assert!(r > 200 && g < 100 && b < 100);
+
+        // sRGB のプロファイルは変換しない
+        let srgb = ColorProfile::new_srgb().encode().unwrap();
+        let mut unchanged = image();
+        convert_to_srgb(&mut unchanged, &srgb).unwrap();
+        assert_eq!(unchanged.to_rgb8().get_pixel(0, 0), &Rgb([200, 100, 100]));
+    }
+}
@@ -1,5 +1,8 @@
 use std::path::Path;
 
+use crate::utils::archive::{get_archive_cover, ArchiveOptions};
+use crate::utils::format_registry::{media_kind_from_path, MediaKind};
+
 pub(crate) fn get_parent_dir(path: &str) -> String {
     Path::new(path)
         .parent()
@@ -34,90 +37,15 @@ pub(crate) fn is_executable_file(path: &str) -> bool {
 }
 
 pub(crate) fn is_image_file(path: &str) -> bool {
-    let ext = Path::new(path)
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default();
-    get_image_extensions().iter().any(|v| *v == ext)
+    media_kind_from_path(path) == Some(MediaKind::Image)
 }
 
 pub(crate) fn is_video_file(path: &str) -> bool {
-    let ext = Path::new(path)
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default();
-    get_video_extensions().iter().any(|v| *v == ext)
+    media_kind_from_path(path) == Some(MediaKind::Video)
 }
 
 pub(crate) fn is_compressed_file(path: &str) -> bool {
-    let ext = Path::new(path)
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default();
-    get_compressed_extensions().iter().any(|v| *v == ext)
-}
-
-pub(crate) fn get_jpeg_extensions() -> Vec<String> {
-    ["jpg", "jpeg", "JPG", "JPEG", "jpe", "jfif", "pjpeg", "pjp"]
-        .iter()
-        .map(|v| v.to_string())
-        .collect()
-}
-
-pub(crate) fn get_png_extensions() -> Vec<String> {
-    ["png", "PNG"].iter().map(|v| v.to_string()).collect()
-}
-
-pub(crate) fn get_gif_extensions() -> Vec<String> {
-    ["gif"].iter().map(|v| v.to_string()).collect()
-}
-
-pub(crate) fn get_tiff_extensions() -> Vec<String> {
-    ["tif", "tiff"].iter().map(|v| v.to_string()).collect()
-}
-
-pub(crate) fn get_bmp_extensions() -> Vec<String> {
-    ["bmp", "dib"].iter().map(|v| v.to_string()).collect()
-}
-
-pub(crate) fn get_webp_extensions() -> Vec<String> {
-    ["webp"].iter().map(|v| v.to_string()).collect()
-}
-
-pub(crate) fn get_image_extensions() -> Vec<String> {
-    let mut extensions = vec![];
-    extensions.extend(get_jpeg_extensions());
-    extensions.extend(get_png_extensions());
-    extensions.extend(get_gif_extensions());
-    extensions.extend(get_tiff_extensions());
-    extensions.extend(get_bmp_extensions());
-    extensions.extend(get_webp_extensions());
-    extensions
-}
-
-pub(crate) fn get_video_extensions() -> Vec<String> {
-    ["mp4", "avi", "mov", "mkv", "wmv", "flv", "webm"]
-        .iter()
-        .map(|v| v.to_string())
-        .collect()
-}
-
-pub(crate) fn get_compressed_extensions() -> Vec<String> {
-    ["zip", "tar", "gz", "bz2", "xz", "7z"]
-        .iter()
-        .map(|v| v.to_string())
-        .collect()
-}
-
-pub(crate) fn get_any_extensions() -> Vec<String> {
-    let mut extensions = vec![];
-    extensions.extend(get_image_extensions());
-    extensions.extend(get_video_extensions());
-    extensions.extend(get_compressed_extensions());
-    extensions
+    media_kind_from_path(path) == Some(MediaKind::Archive)
 }
 
 /// パスを正規化（Windowsのバックスラッシュを統一）
@@ -125,21 +53,37 @@ pub(crate) fn normalize_path(path: &str) -> String {
     path.replace('\\', "/").to_lowercase()
 }
 
+/// フォルダまたはアーカイブの代表画像のパスを返す
+/// アーカイブの場合は中の最初の画像から作成した表紙画像のパスを返す（`archive_options` の設定で開く）
+/// 画像が見つからない場合は空文字列を返す
+pub(crate) fn find_thumbnail_image(path: &Path, archive_options: &ArchiveOptions) -> String {
+    if path.is_dir() {
+        return find_first_image_in_folder(path);
+    }
+    let path = path.to_str().unwrap_or_default();
+    if !is_compressed_file(path) {
+        return String::new();
+    }
+    match get_archive_cover(path, archive_options) {
+        Ok(cover) => cover
+            .and_then(|p| p.to_str().map(str::to_string))
+            .unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Failed to extract archive cover: {:#}", e);
+            String::new()
+        }
+    }
+}
+
 /// フォルダ内の最初の画像ファイルのパスを返す
 /// 画像が見つからない場合は空文字列を返す
 pub(crate) fn find_first_image_in_folder(folder_path: &std::path::Path) -> String {
     use std::fs::read_dir;
 
-    let extensions = get_image_extensions();
-
     if let Ok(entries) = read_dir(folder_path) {
         for entry in entries.flatten() {
             let path = entry.path();
-            let ext = path
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or_default();
-            if extensions.iter().any(|v| v == ext) {
+            if is_image_file(path.to_str().unwrap_or_default()) {
                 return path.to_str().unwrap_or_default().to_string();
             }
         }
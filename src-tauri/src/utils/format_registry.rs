@@ -0,0 +1,447 @@
+//! 対応するファイル形式の一覧
+//!
+//! 形式ごとのメディアの種類・MIME タイプ・拡張子・先頭のバイト列（マジックバイト）をまとめ、
+//! ファイルツリーの構築、ファイル選択ダイアログのフィルタ、Explorer、サムネイルの作成で同じ判定を使う。
+//! 拡張子は大文字小文字を区別せずに照合し、拡張子のないファイルは先頭のバイト列で判定する。
+//! 設定でユーザーが追加した拡張子も画像・動画として扱う
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::utils::svg_utils::is_svg;
+use MediaKind::{Archive, Image, Video};
+
+/// メディアの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MediaKind {
+    Image,
+    Video,
+    /// 中の画像を表示できるアーカイブ
+    Archive,
+}
+
+/// 先頭のバイト列の判定条件（すべての (位置, バイト列) が一致すれば該当する）
+type Signature = &'static [(usize, &'static [u8])];
+
+/// ファイル形式
+pub(crate) struct MediaFormat {
+    pub kind: MediaKind,
+    pub mime_type: &'static str,
+    /// 小文字の拡張子（`tar.gz` のような二重拡張子を含む）
+    pub extensions: &'static [&'static str],
+    /// いずれかが一致すればこの形式とみなす先頭のバイト列
+    signatures: &'static [Signature],
+}
+
+const fn format(
+    kind: MediaKind,
+    mime_type: &'static str,
+    extensions: &'static [&'static str],
+    signatures: &'static [Signature],
+) -> MediaFormat {
+    MediaFormat {
+        kind,
+        mime_type,
+        extensions,
+        signatures,
+    }
+}
+
+const SVG_MIME_TYPE: &str = "image/svg+xml";
+
+/// 対応する形式の一覧（先頭のバイト列は上から順に判定する）
+static FORMATS: &[MediaFormat] = &[
+    format(
+        Image,
+        "image/jpeg",
+        &["jpg", "jpeg", "jpe", "jfif", "pjpeg", "pjp"],
+        &[&[(0, b"\xFF\xD8\xFF")]],
+    ),
+    format(
+        Image,
+        "image/png",
+        &["png"],
+        &[&[(0, b"\x89PNG\r\n\x1A\n")]],
+    ),
+    format(
+        Image,
+        "image/gif",
+        &["gif"],
+        &[&[(0, b"GIF87a")], &[(0, b"GIF89a")]],
+    ),
+    // CR2 / NEF / ARW / DNG は TIFF と同じヘッダを持つため、先頭のバイト列では TIFF と判定される
+    format(
+        Image,
+        "image/x-raw",
+        &["cr2", "cr3", "nef", "arw", "dng", "raf"],
+        &[&[(4, b"ftypcrx ")], &[(0, b"FUJIFILMCCD-RAW")]],
+    ),
+    format(
+        Image,
+        "image/tiff",
+        &["tif", "tiff"],
+        &[&[(0, b"II*\0")], &[(0, b"MM\0*")]],
+    ),
+    // "BM" の 2 バイトだけでは誤判定しやすいため、予約領域 (0) と DIB ヘッダの大きさも確認する
+    format(
+        Image,
+        "image/bmp",
+        &["bmp", "dib"],
+        &[
+            &[(0, b"BM"), (6, b"\0\0\0\0"), (14, b"\x0C\0\0\0")],
+            &[(0, b"BM"), (6, b"\0\0\0\0"), (14, b"\x28\0\0\0")],
+            &[(0, b"BM"), (6, b"\0\0\0\0"), (14, b"\x38\0\0\0")],
+            &[(0, b"BM"), (6, b"\0\0\0\0"), (14, b"\x6C\0\0\0")],
+            &[(0, b"BM"), (6, b"\0\0\0\0"), (14, b"\x7C\0\0\0")],
+        ],
+    ),
+    format(
+        Image,
+        "image/webp",
+        &["webp"],
+        &[&[(0, b"RIFF"), (8, b"WEBP")]],
+    ),
+    format(
+        Image,
+        "image/avif",
+        &["avif"],
+        &[&[(4, b"ftypavif")], &[(4, b"ftypavis")]],
+    ),
+    format(
+        Image,
+        "image/heif",
+        &["heic", "heif", "hif"],
+        &[
+            &[(4, b"ftypheic")],
+            &[(4, b"ftypheix")],
+            &[(4, b"ftypheim")],
+            &[(4, b"ftypheis")],
+            &[(4, b"ftyphevc")],
+            &[(4, b"ftyphevx")],
+            &[(4, b"ftypmif1")],
+            &[(4, b"ftypmsf1")],
+        ],
+    ),
+    format(
+        Image,
+        "image/jxl",
+        &["jxl"],
+        &[&[(0, b"\xFF\x0A")], &[(0, b"\0\0\0\x0CJXL \r\n\x87\n")]],
+    ),
+    // SVG はテキストのため先頭のバイト列ではなく `is_svg` で判定する
+    format(Image, SVG_MIME_TYPE, &["svg"], &[]),
+    format(Image, "image/x-icon", &["ico"], &[&[(0, b"\0\0\x01\0")]]),
+    // TGA は先頭に識別子を持たないため拡張子でのみ判定する
+    format(Image, "image/x-tga", &["tga"], &[]),
+    format(Image, "image/qoi", &["qoi"], &[&[(0, b"qoif")]]),
+    format(Image, "image/vnd.ms-dds", &["dds"], &[&[(0, b"DDS ")]]),
+    format(
+        Image,
+        "image/vnd.adobe.photoshop",
+        &["psd"],
+        &[&[(0, b"8BPS")]],
+    ),
+    format(
+        Image,
+        "image/x-exr",
+        &["exr"],
+        &[&[(0, b"\x76\x2F\x31\x01")]],
+    ),
+    format(
+        Image,
+        "image/vnd.radiance",
+        &["hdr"],
+        &[&[(0, b"#?RADIANCE")], &[(0, b"#?RGBE")]],
+    ),
+    format(
+        Video,
+        "video/mp4",
+        &["mp4"],
+        &[
+            &[(4, b"ftypisom")],
+            &[(4, b"ftypiso2")],
+            &[(4, b"ftypmp41")],
+            &[(4, b"ftypmp42")],
+            &[(4, b"ftypavc1")],
+            &[(4, b"ftypM4V ")],
+            &[(4, b"ftypdash")],
+        ],
+    ),
+    format(
+        Video,
+        "video/x-msvideo",
+        &["avi"],
+        &[&[(0, b"RIFF"), (8, b"AVI ")]],
+    ),
+    format(
+        Video,
+        "video/quicktime",
+        &["mov"],
+        &[&[(4, b"ftypqt  ")], &[(4, b"moov")]],
+    ),
+    // Matroska と WebM は同じ EBML ヘッダを持つため、先頭のバイト列では Matroska と判定される
+    format(
+        Video,
+        "video/x-matroska",
+        &["mkv"],
+        &[&[(0, b"\x1A\x45\xDF\xA3")]],
+    ),
+    format(
+        Video,
+        "video/x-ms-wmv",
+        &["wmv"],
+        &[&[(0, b"\x30\x26\xB2\x75\x8E\x66\xCF\x11")]],
+    ),
+    format(Video, "video/x-flv", &["flv"], &[&[(0, b"FLV\x01")]]),
+    format(Video, "video/webm", &["webm"], &[]),
+    format(
+        Archive,
+        "application/zip",
+        &["zip", "cbz"],
+        &[&[(0, b"PK\x03\x04")], &[(0, b"PK\x05\x06")]],
+    ),
+    format(
+        Archive,
+        "application/x-tar",
+        &["tar", "cbt"],
+        &[&[(257, b"ustar")]],
+    ),
+    // 単体の .gz / .bz2 / .xz（`image.png.gz` など）は TAR ではないため対象外
+    format(
+        Archive,
+        "application/gzip",
+        &["tar.gz", "tgz"],
+        &[&[(0, b"\x1F\x8B")]],
+    ),
+    format(
+        Archive,
+        "application/x-bzip2",
+        &["tar.bz2", "tbz2", "tbz"],
+        &[&[(0, b"BZh")]],
+    ),
+    format(
+        Archive,
+        "application/x-xz",
+        &["tar.xz", "txz"],
+        &[&[(0, b"\xFD7zXZ\0")]],
+    ),
+    format(
+        Archive,
+        "application/x-7z-compressed",
+        &["7z", "cb7"],
+        &[&[(0, b"7z\xBC\xAF\x27\x1C")]],
+    ),
+    format(
+        Archive,
+        "application/vnd.rar",
+        &["rar", "cbr"],
+        &[&[(0, b"Rar!\x1A\x07")]],
+    ),
+];
+
+/// 先頭のバイト列で形式を判定するために読み込むバイト数（tar の ustar が 257 バイト目にあるため）
+const SNIFF_LENGTH: usize = 512;
+
+/// 拡張子の設定（ユーザーが追加した拡張子）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatSettings {
+    /// 画像として扱う拡張子
+    #[serde(default)]
+    pub image_extensions: Vec<String>,
+    /// 動画として扱う拡張子
+    #[serde(default)]
+    pub video_extensions: Vec<String>,
+}
+
+static FORMAT_SETTINGS: RwLock<FormatSettings> = RwLock::new(FormatSettings {
+    image_extensions: Vec::new(),
+    video_extensions: Vec::new(),
+});
+
+/// 拡張子の設定を反映する
+/// 拡張子は先頭の `.` を除いて小文字にそろえ、空のものと重複を取り除く
+pub(crate) fn set_format_settings(settings: FormatSettings) {
+    let normalize = |extensions: Vec<String>| {
+        let mut normalized: Vec<String> = vec![];
+        for ext in extensions {
+            let ext = ext.trim().trim_start_matches('.').to_lowercase();
+            if !ext.is_empty() && !normalized.contains(&ext) {
+                normalized.push(ext);
+            }
+        }
+        normalized
+    };
+    let settings = FormatSettings {
+        image_extensions: normalize(settings.image_extensions),
+        video_extensions: normalize(settings.video_extensions),
+    };
+    if let Ok(mut current) = FORMAT_SETTINGS.write() {
+        *current = settings;
+    }
+}
+
+/// 現在の拡張子の設定
+pub(crate) fn format_settings() -> FormatSettings {
+    FORMAT_SETTINGS
+        .read()
+        .map(|settings| settings.clone())
+        .unwrap_or_default()
+}
+
+/// パスの拡張子（小文字）
+fn extension_of(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+}
+
+/// パスが `.<ext>` で終わるか（大文字小文字を区別しない。`.zip` のような拡張子だけの名前は除く）
+fn has_extension(lower_path: &str, ext: &str) -> bool {
+    lower_path
+        .strip_suffix(ext)
+        .and_then(|rest| rest.strip_suffix('.'))
+        .is_some_and(|stem| !stem.is_empty() && !stem.ends_with(['/', '\\']))
+}
+
+/// 拡張子から形式を判定する（`.tar.gz` のような二重拡張子も考慮する）
+pub(crate) fn format_from_path(path: &str) -> Option<&'static MediaFormat> {
+    let lower = path.to_lowercase();
+    FORMATS.iter().find(|format| {
+        format
+            .extensions
+            .iter()
+            .any(|ext| has_extension(&lower, ext))
+    })
+}
+
+/// 拡張子からメディアの種類を判定する（ユーザーが追加した拡張子を含む）
+pub(crate) fn media_kind_from_path(path: &str) -> Option<MediaKind> {
+    if let Some(format) = format_from_path(path) {
+        return Some(format.kind);
+    }
+    let ext = extension_of(path)?;
+    let settings = FORMAT_SETTINGS.read().ok()?;
+    if settings.image_extensions.contains(&ext) {
+        Some(Image)
+    } else if settings.video_extensions.contains(&ext) {
+        Some(Video)
+    } else {
+        None
+    }
+}
+
+/// 先頭のバイト列から形式を判定する
+pub(crate) fn sniff_format(data: &[u8]) -> Option<&'static MediaFormat> {
+    let matches = |signature: &Signature| {
+        signature.iter().all(|(offset, bytes)| {
+            data.get(*offset..offset + bytes.len())
+                .is_some_and(|head| head == *bytes)
+        })
+    };
+    FORMATS
+        .iter()
+        .find(|format| format.signatures.iter().any(matches))
+        .or_else(|| {
+            is_svg(data)
+                .then(|| FORMATS.iter().find(|f| f.mime_type == SVG_MIME_TYPE))
+                .flatten()
+        })
+}
+
+/// ファイルのメディアの種類を判定する
+/// 拡張子のないファイルは先頭のバイト列で画像・動画かどうかを判定する
+/// （未対応の拡張子のファイルまで読み込むと、フォルダを開くたびにすべてのファイルを読むことになるため）
+/// （アーカイブは拡張子で形式を選んで開くため、先頭のバイト列ではアーカイブと判定しない）
+pub(crate) fn detect_media_kind(path: &Path) -> Option<MediaKind> {
+    let path_str = path.to_str().unwrap_or_default();
+    if let Some(kind) = media_kind_from_path(path_str) {
+        return Some(kind);
+    }
+    if extension_of(path_str).is_some() {
+        return None;
+    }
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)
+        .ok()?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut head)
+        .ok()?;
+    sniff_format(&head)
+        .map(|format| format.kind)
+        .filter(|kind| *kind != Archive)
+}
+
+/// 拡張子から MIME タイプを判定する
+pub(crate) fn mime_type_from_path(path: &str) -> Option<&'static str> {
+    format_from_path(path).map(|format| format.mime_type)
+}
+
+/// 拡張子から MIME タイプを判定し、判定できなければ先頭のバイト列で判定する
+/// （どちらでも判定できない場合は application/octet-stream）
+pub(crate) fn detect_mime_type(path: &str, data: &[u8]) -> &'static str {
+    mime_type_from_path(path)
+        .or_else(|| sniff_format(data).map(|format| format.mime_type))
+        .unwrap_or("application/octet-stream")
+}
+
+/// 指定した種類の拡張子の一覧（ユーザーが追加した拡張子を含む）
+pub(crate) fn extensions_of(kind: MediaKind) -> Vec<String> {
+    let mut extensions: Vec<String> = FORMATS
+        .iter()
+        .filter(|format| format.kind == kind)
+        .flat_map(|format| format.extensions.iter().map(|ext| ext.to_string()))
+        .collect();
+    let settings = format_settings();
+    match kind {
+        Image => extensions.extend(settings.image_extensions),
+        Video => extensions.extend(settings.video_extensions),
+        Archive => {}
+    }
+    extensions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_kind_from_path_ignores_case() {
+        assert_eq!(media_kind_from_path("a/b.JPG"), Some(Image));
+        assert_eq!(media_kind_from_path("a/b.Gif"), Some(Image));
+        assert_eq!(media_kind_from_path("a/b.WEBP"), Some(Image));
+        assert_eq!(media_kind_from_path("a/b.MP4"), Some(Video));
+        assert_eq!(media_kind_from_path("a/b.CBZ"), Some(Archive));
+        assert_eq!(media_kind_from_path("a/b.txt"), None);
+        assert_eq!(media_kind_from_path("a/b"), None);
+        assert_eq!(media_kind_from_path("a/b.TAR.GZ"), Some(Archive));
+        assert_eq!(media_kind_from_path("a/b.tbz"), Some(Archive));
+        assert_eq!(media_kind_from_path("a/image.png.gz"), None);
+        assert_eq!(media_kind_from_path("a/.zip"), None);
+        assert_eq!(mime_type_from_path("a/b.HEIC"), Some("image/heif"));
+    }
+
+    #[test]
+    fn test_sniff_format() {
+        let kind = |data: &[u8]| sniff_format(data).map(|f| f.mime_type);
+        assert_eq!(kind(b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR"), Some("image/png"));
+        assert_eq!(kind(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(kind(b"RIFF\0\0\0\0AVI LIST"), Some("video/x-msvideo"));
+        assert_eq!(kind(b"\0\0\0\x1Cftypheic\0\0\0\0"), Some("image/heif"));
+        assert_eq!(kind(b"\0\0\0\x20ftypisom\0\0\x02\0"), Some("video/mp4"));
+        assert_eq!(
+            kind(b"<?xml version=\"1.0\"?><svg></svg>"),
+            Some(SVG_MIME_TYPE)
+        );
+        assert_eq!(
+            kind(b"BM\x36\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0"),
+            Some("image/bmp")
+        );
+        assert_eq!(kind(b"BMW is a car maker"), None);
+        assert_eq!(kind(b"hello"), None);
+        assert_eq!(kind(b""), None);
+    }
+}
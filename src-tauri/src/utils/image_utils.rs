@@ -0,0 +1,437 @@
+//! 表示用の画像処理ユーティリティ
+//!
+//! スキャン画像のような巨大な画像をそのまま WebView に渡すと、転送とデコードに時間がかかり
+//! メモリも大きく消費する。画面に収まる大きさに縮小した表示用の画像を作り、
+//! 元の解像度の画像は拡大表示したときだけ読み込む
+//!
+//! HEIC / HEIF と JPEG XL は WebView が表示できないため、バックエンドでデコードして
+//! PNG / JPEG に変換してから渡す。これらのデコーダは `image` のデコードフックとして登録し、
+//! サムネイルや埋め込みの生成でも他の形式と同じように読み込めるようにする。
+//! カメラの RAW 画像・PSD（統合された画像）・TGA / QOI / DDS / OpenEXR / Radiance HDR も
+//! 同様に変換する。HDR の画像は 8 bit にトーンマッピングする。SVG は WebView がそのまま
+//! 表示できるため、縮小画像やサムネイルを作るときだけ `svg_utils` で描画する
+//!
+//! デコードした画像には EXIF の Orientation を適用し、縦向きで撮影した写真が横倒しのまま
+//! 縮小・サムネイル化・埋め込みされないようにする。埋め込みの ICC プロファイルがあれば
+//! `color_utils` で sRGB に変換する（RAW / PSD / SVG は対象外）
+
+use anyhow::{anyhow, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, PngEncoder};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat, ImageReader, Rgba};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Once;
+
+use crate::utils::color_utils::convert_to_srgb;
+use crate::utils::format_registry;
+use crate::utils::raw_utils::{decode_raw_image, is_raw_image, read_raw_orientation};
+use crate::utils::svg_utils::{is_svg, render_svg, svg_dimensions};
+
+/// 表示用 JPEG の品質
+const DISPLAY_JPEG_QUALITY: u8 = 90;
+/// 大きさを指定せずに SVG を描画するとき（サムネイル・埋め込みなど）の長辺
+const SVG_DEFAULT_SIZE: u32 = 1024;
+
+/// HEIC / HEIF / AVIF (libheif) と JPEG XL のデコーダを `image` に登録する
+/// 登録は初回の呼び出しでだけ行う。libheif は `heic` フィーチャーが有効な場合のみ
+fn register_decoding_hooks() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        #[cfg(feature = "heic")]
+        libheif_rs::integration::image::register_all_decoding_hooks();
+        jxl_oxide::integration::register_image_decoding_hook();
+    });
+}
+
+/// 形式を推測したメモリ上の画像のリーダーを作る
+fn image_reader(data: &[u8]) -> Result<ImageReader<Cursor<&[u8]>>> {
+    register_decoding_hooks();
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .context("failed to read image")
+}
+
+/// `image` の ImageReader では読み込めない形式
+enum SpecialFormat {
+    /// カメラの RAW 画像
+    Raw,
+    /// Photoshop のドキュメント
+    Psd,
+    Svg,
+}
+
+/// `image` の ImageReader では読み込めない形式を判定する
+/// CR2 / NEF / ARW / DNG などは TIFF 形式のため、`image` では TIFF と判定される前に確認する
+/// 通常の TIFF はカメラの情報を持たないため RAW とは判定されない
+fn detect_special_format(data: &[u8]) -> Option<SpecialFormat> {
+    if data.starts_with(b"8BPS") {
+        Some(SpecialFormat::Psd)
+    } else if is_svg(data) {
+        Some(SpecialFormat::Svg)
+    } else if matches!(image::guess_format(data), Ok(ImageFormat::Tiff) | Err(_))
+        && is_raw_image(data)
+    {
+        Some(SpecialFormat::Raw)
+    } else {
+        None
+    }
+}
+
+/// PSD の統合された画像をデコードする
+fn decode_psd(data: &[u8]) -> Result<DynamicImage> {
+    let psd = psd::Psd::from_bytes(data).map_err(|e| anyhow!("failed to read psd: {}", e))?;
+    let image = ImageBuffer::<Rgba<u8>, _>::from_raw(psd.width(), psd.height(), psd.rgba())
+        .ok_or_else(|| anyhow!("failed to decode psd"))?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// 浮動小数点の HDR の画像 (OpenEXR / Radiance HDR など) を 8 bit にトーンマッピングする
+/// リニアな値を Reinhard 法 (x / (1 + x)) で圧縮し、sRGB のガンマを適用する
+fn tone_map(image: DynamicImage) -> DynamicImage {
+    if !matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    ) {
+        return image;
+    }
+    let has_alpha = image.color().has_alpha();
+    let to_srgb = |v: f32| {
+        let v = v.max(0.0);
+        let v = v / (1.0 + v);
+        let v = if v <= 0.003_130_8 {
+            v * 12.92
+        } else {
+            1.055 * v.powf(1.0 / 2.4) - 0.055
+        };
+        (v * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    let hdr = image.into_rgba32f();
+    let mapped = ImageBuffer::from_fn(hdr.width(), hdr.height(), |x, y| {
+        let [r, g, b, a] = hdr.get_pixel(x, y).0;
+        Rgba([
+            to_srgb(r),
+            to_srgb(g),
+            to_srgb(b),
+            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    });
+    let mapped = DynamicImage::ImageRgba8(mapped);
+    match has_alpha {
+        true => mapped,
+        false => DynamicImage::ImageRgb8(mapped.to_rgb8()),
+    }
+}
+
+/// HEIC / HEIF / AVIF の ICC プロファイルを libheif で読み取る
+/// （libheif のデコードフックは `ImageDecoder::icc_profile` でプロファイルを返さないため）
+#[cfg(feature = "heic")]
+fn read_heif_icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let context = libheif_rs::HeifContext::read_from_bytes(data).ok()?;
+    let handle = context.primary_image_handle().ok()?;
+    handle.color_profile_raw().map(|profile| profile.data)
+}
+
+/// `heic` フィーチャーが無効な場合は HEIC / HEIF / AVIF をデコードできないため、プロファイルも読まない
+#[cfg(not(feature = "heic"))]
+fn read_heif_icc_profile(_data: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// メモリ上の画像をデコードし、EXIF の Orientation に従って回転・反転する
+/// ICC プロファイルを持つ画像は sRGB に変換する
+/// 巨大な画像も扱えるよう、`image` の既定のメモリ上限は外す
+pub(crate) fn decode_image(data: &[u8]) -> Result<DynamicImage> {
+    match detect_special_format(data) {
+        Some(SpecialFormat::Raw) => return decode_raw_image(data),
+        Some(SpecialFormat::Psd) => return decode_psd(data),
+        Some(SpecialFormat::Svg) => return render_svg(data, SVG_DEFAULT_SIZE),
+        None => {}
+    }
+    let mut reader = image_reader(data)?;
+    reader.no_limits();
+    let mut decoder = reader.into_decoder().context("failed to read image")?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let icc_profile = decoder.icc_profile().ok().flatten();
+    let mut image = DynamicImage::from_decoder(decoder).context("failed to decode image")?;
+    image.apply_orientation(orientation);
+    let icc_profile = icc_profile.or_else(|| {
+        matches!(
+            format_registry::sniff_format(data).map(|format| format.mime_type),
+            Some("image/heif" | "image/avif")
+        )
+        .then(|| read_heif_icc_profile(data))
+        .flatten()
+    });
+    if let Some(icc_profile) = icc_profile {
+        // 解釈できないプロファイルは無視し、元の画素値のまま表示する
+        if let Err(e) = convert_to_srgb(&mut image, &icc_profile) {
+            eprintln!("Failed to apply icc profile: {:#}", e);
+        }
+    }
+    Ok(tone_map(image))
+}
+
+/// 画像ファイルをデコードする
+pub(crate) fn decode_image_file(path: &Path) -> Result<DynamicImage> {
+    let data = std::fs::read(path).context("failed to read image file")?;
+    decode_image(&data)
+}
+
+/// EXIF の Orientation を適用した後の画像の大きさを取得する（RAW 画像以外はヘッダのみ読み込む）
+/// SVG は `decode_image` で描画する大きさを返す
+pub(crate) fn image_dimensions(data: &[u8]) -> Result<(u32, u32)> {
+    match detect_special_format(data) {
+        Some(SpecialFormat::Raw) => {
+            let image = decode_raw_image(data)?;
+            return Ok((image.width(), image.height()));
+        }
+        Some(SpecialFormat::Psd) => return psd_dimensions(data),
+        Some(SpecialFormat::Svg) => return svg_dimensions(data, SVG_DEFAULT_SIZE),
+        None => {}
+    }
+    let mut decoder = image_reader(data)?
+        .into_decoder()
+        .context("failed to read image size")?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let (width, height) = decoder.dimensions();
+    Ok(match orientation {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    })
+}
+
+/// PSD のヘッダから大きさを読み取る（高さ・幅の順に 14 バイト目から格納されている）
+fn psd_dimensions(data: &[u8]) -> Result<(u32, u32)> {
+    let read_u32 = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| anyhow!("failed to read psd size"))
+    };
+    Ok((read_u32(18)?, read_u32(14)?))
+}
+
+/// 画像の形式の MIME タイプを内容から判定する
+/// `image` の ImageReader で読み込めない形式や、`image` が判定できない形式（HEIC / JPEG XL など）は
+/// `name` の拡張子と先頭のバイト列で判定する
+pub(crate) fn detect_mime_type(data: &[u8], name: &str) -> &'static str {
+    match image::guess_format(data) {
+        Ok(format) if detect_special_format(data).is_none() => format.to_mime_type(),
+        _ => format_registry::detect_mime_type(name, data),
+    }
+}
+
+/// 画像の EXIF の Orientation を取得する（Orientation がなければ NoTransforms）
+pub(crate) fn image_orientation(data: &[u8]) -> Result<Orientation> {
+    match detect_special_format(data) {
+        Some(SpecialFormat::Raw) => {
+            return Ok(read_raw_orientation(data).unwrap_or(Orientation::NoTransforms));
+        }
+        Some(SpecialFormat::Psd | SpecialFormat::Svg) => return Ok(Orientation::NoTransforms),
+        None => {}
+    }
+    let mut decoder = image_reader(data)?
+        .into_decoder()
+        .context("failed to read image")?;
+    Ok(decoder.orientation().unwrap_or(Orientation::NoTransforms))
+}
+
+/// AVIF か
+/// libheif のデコードフックが AVIF の形式判定を先に引き受けるため、ImageReader の形式は
+/// `ImageFormat::Avif` にならない。WebView が表示できる形式なので内容から判定して区別する
+fn is_avif(data: &[u8]) -> bool {
+    format_registry::sniff_format(data).map(|format| format.mime_type) == Some("image/avif")
+}
+
+/// アニメーション AVIF（AVIF の画像シーケンス）か
+fn is_animated_avif(data: &[u8]) -> bool {
+    data.get(4..12) == Some(b"ftypavis".as_slice())
+}
+
+/// アニメーション PNG (APNG) か（IDAT より前に acTL チャンクがあるか）
+fn is_animated_png(data: &[u8]) -> bool {
+    if data.get(..8) != Some(b"\x89PNG\r\n\x1A\n".as_slice()) {
+        return false;
+    }
+    let mut offset = 8;
+    while let Some(header) = data.get(offset..offset + 8) {
+        match &header[4..] {
+            b"acTL" => return true,
+            b"IDAT" | b"IEND" => return false,
+            _ => {}
+        }
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        // チャンク長・種類・データ・CRC
+        offset = match offset.checked_add(length).and_then(|o| o.checked_add(12)) {
+            Some(next) => next,
+            None => return false,
+        };
+    }
+    false
+}
+
+/// アニメーション WebP か（VP8X チャンクのアニメーションのフラグが立っているか）
+fn is_animated_webp(data: &[u8]) -> bool {
+    data.get(..4) == Some(b"RIFF".as_slice())
+        && data.get(8..16) == Some(b"WEBPVP8X".as_slice())
+        && data.get(20).is_some_and(|flags| flags & 0x02 != 0)
+}
+
+/// 縮小するとアニメーションが失われる画像か
+fn is_animated_image(reader: &ImageReader<Cursor<&[u8]>>, data: &[u8]) -> bool {
+    match reader.format() {
+        Some(ImageFormat::Gif) => true,
+        Some(ImageFormat::Png) => is_animated_png(data),
+        Some(ImageFormat::WebP) => is_animated_webp(data),
+        _ => is_animated_avif(data),
+    }
+}
+
+/// WebView が表示できない形式か（`image` の ImageReader で読み込めない形式は別に判定する）
+/// HEIC / HEIF / JPEG XL は `image` の組み込みの形式ではなく、登録したデコードフックでのみ読み込める
+/// TIFF も WebView2 / WebKitGTK では表示できないため変換する
+fn needs_conversion(reader: &ImageReader<Cursor<&[u8]>>, data: &[u8]) -> bool {
+    !is_avif(data)
+        && !matches!(
+            reader.format(),
+            Some(
+                ImageFormat::Png
+                    | ImageFormat::Jpeg
+                    | ImageFormat::Gif
+                    | ImageFormat::WebP
+                    | ImageFormat::Bmp
+                    | ImageFormat::Ico
+                    | ImageFormat::Avif
+            )
+        )
+}
+
+/// WebView で表示できる画像に変換する
+/// WebView がそのまま表示できる形式（SVG を含む）は None を返す
+pub(crate) fn create_viewable_image(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    match detect_special_format(data) {
+        Some(SpecialFormat::Svg) => return Ok(None),
+        Some(SpecialFormat::Raw | SpecialFormat::Psd) => {
+            return encode_display_image(&decode_image(data)?).map(Some);
+        }
+        None => {}
+    }
+    if !needs_conversion(&image_reader(data)?, data) {
+        return Ok(None);
+    }
+    encode_display_image(&decode_image(data)?).map(Some)
+}
+
+/// 長辺が `max_size` を超える画像を縮小した表示用の画像を作る
+/// 縮小の必要がない画像や、縮小するとアニメーションが失われる GIF・APNG・アニメーション WebP / AVIF は
+/// None を返す
+/// WebView が表示できない形式は縮小の必要がなくても変換する
+/// SVG は長辺が `max_size` になるように描画する
+/// 透過のある画像は PNG、それ以外は JPEG でエンコードする
+pub(crate) fn create_display_image(data: &[u8], max_size: u32) -> Result<Option<Vec<u8>>> {
+    match detect_special_format(data) {
+        Some(SpecialFormat::Svg) => {
+            return encode_display_image(&render_svg(data, max_size)?).map(Some);
+        }
+        // RAW 画像はプレビューの大きさがデコードするまでわからないため、常にデコードして変換する
+        Some(SpecialFormat::Raw | SpecialFormat::Psd) => {
+            let mut image = decode_image(data)?;
+            if image.width().max(image.height()) > max_size {
+                image = image.resize(max_size, max_size, FilterType::Triangle);
+            }
+            return encode_display_image(&image).map(Some);
+        }
+        None => {}
+    }
+    let reader = image_reader(data)?;
+    if is_animated_image(&reader, data) {
+        return Ok(None);
+    }
+    let convert = needs_conversion(&reader, data);
+    // 縮小が必要かはヘッダの大きさだけで判定し、小さな画像のデコードを省く
+    let (width, height) = reader
+        .into_dimensions()
+        .context("failed to read image size")?;
+    if width.max(height) <= max_size {
+        return match convert {
+            true => encode_display_image(&decode_image(data)?).map(Some),
+            false => Ok(None),
+        };
+    }
+
+    let image = decode_image(data)?.resize(max_size, max_size, FilterType::Triangle);
+    encode_display_image(&image).map(Some)
+}
+
+/// 表示用の画像をエンコードする（透過があれば PNG、なければ JPEG）
+fn encode_display_image(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if image.color().has_alpha() {
+        let encoder = PngEncoder::new_with_quality(
+            &mut buf,
+            CompressionType::Fast,
+            image::codecs::png::FilterType::Adaptive,
+        );
+        image.write_with_encoder(encoder)?;
+    } else {
+        let encoder = JpegEncoder::new_with_quality(&mut buf, DISPLAY_JPEG_QUALITY);
+        image.to_rgb8().write_with_encoder(encoder)?;
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64x64 の PNG
+    fn still_png() -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::new_rgba8(64, 64)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// 64x64 の PNG の IHDR の直後に acTL チャンクを挿入した APNG
+    fn animated_png() -> Vec<u8> {
+        let mut png = still_png();
+        // シグネチャ (8) + IHDR (長さ 4 + 種類 4 + データ 13 + CRC 4)
+        let ihdr_end = 8 + 25;
+        let mut actl = vec![0, 0, 0, 8];
+        actl.extend_from_slice(b"acTL");
+        actl.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 0]);
+        actl.extend_from_slice(&[0; 4]);
+        png.splice(ihdr_end..ihdr_end, actl);
+        png
+    }
+
+    /// 64x64 のアニメーション WebP のヘッダ（VP8X のアニメーションのフラグを立てたもの）
+    fn animated_webp() -> Vec<u8> {
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X".to_vec();
+        webp.extend_from_slice(&[10, 0, 0, 0, 0x12, 0, 0, 0, 63, 0, 0, 63, 0, 0]);
+        webp.extend_from_slice(b"ANIM\x06\0\0\0\0\0\0\0\0\0");
+        webp
+    }
+
+    #[test]
+    fn test_create_display_image_keeps_animation() {
+        let png = animated_png();
+        assert!(is_animated_png(&png));
+        assert!(create_display_image(&png, 16).unwrap().is_none());
+
+        let webp = animated_webp();
+        assert!(is_animated_webp(&webp));
+        assert!(create_display_image(&webp, 16).unwrap().is_none());
+
+        // アニメーションでない PNG は縮小する
+        let still = still_png();
+        assert!(!is_animated_png(&still));
+        assert!(create_display_image(&still, 16).unwrap().is_some());
+    }
+}
@@ -0,0 +1,204 @@
+//! 表示するファイルのバイト列のメモリキャッシュ
+//!
+//! ページ送りの後に次の画像をディスクやアーカイブから読み始めると、大きな画像や圧縮された
+//! エントリの展開で表示まで待たされる。表示中のファイルの前後を先読みしてメモリに保持し、
+//! カスタム URI スキームからそのまま返す。画面に合わせて縮小した表示用の画像や、
+//! WebView が表示できない形式から変換した画像も同じキャッシュに保持する
+//!
+//! 応答したデータもキャッシュに残し、ページを戻ったときなどに同じ画像を変換し直さないようにする。
+//! 応答には所有したバッファが必要なため複製して渡すが、デコード・変換し直すよりはるかに軽い
+//! （キャッシュからの削除はサイズの上限に任せる）
+
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::utils::archive::{root_archive_path, ArchiveCache, ArchiveOptions};
+use crate::utils::image_utils::{create_display_image, create_viewable_image};
+
+/// キャッシュ全体の最大サイズ
+const MAX_CACHE_SIZE: usize = 256 * 1024 * 1024;
+/// キャッシュする 1 ファイルの最大サイズ（これより大きいファイルは毎回読み込む）
+const MAX_ENTRY_SIZE: usize = 64 * 1024 * 1024;
+
+/// キャッシュするデータの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaVariant {
+    /// ファイルの内容そのもの
+    Original,
+    /// WebView で表示できる形式に変換した原寸の画像
+    Viewable,
+    /// 長辺を指定した大きさ以下に縮小した表示用の画像
+    Display(u32),
+}
+
+/// キャッシュのキー（パス + アーカイブ内のエントリ名 + データの種類 + 実ファイルの更新日時）
+/// ファイルが更新されると更新日時が変わり、古いデータは使われなくなる
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MediaCacheKey {
+    path: String,
+    entry: Option<String>,
+    variant: MediaVariant,
+    modified: Option<SystemTime>,
+}
+
+impl MediaCacheKey {
+    fn new(path: &str, entry: Option<&str>, variant: MediaVariant) -> Self {
+        Self {
+            path: path.to_string(),
+            entry: entry.map(str::to_string),
+            variant,
+            modified: std::fs::metadata(root_archive_path(path))
+                .and_then(|m| m.modified())
+                .ok(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MediaCacheEntries {
+    /// 末尾ほど最近使われたデータ
+    entries: Vec<(MediaCacheKey, Arc<Vec<u8>>)>,
+    /// 保持しているデータの合計サイズ
+    total_size: usize,
+}
+
+/// ファイルのバイト列の LRU キャッシュ（合計サイズで上限を設ける）
+#[derive(Default)]
+pub struct MediaCache {
+    inner: Mutex<MediaCacheEntries>,
+    /// 要求された表示用の最大サイズのうち最大のもの（先読みで同じ大きさの画像を作るため。未要求なら 0）
+    /// フォルダ一覧のサムネイルのような小さな要求でビューアの大きさが上書きされないよう最大値を保持する
+    display_size: AtomicU32,
+}
+
+impl MediaCache {
+    fn get(&self, key: &MediaCacheKey) -> Option<Arc<Vec<u8>>> {
+        let mut inner = self.inner.lock().ok()?;
+        let pos = inner.entries.iter().position(|(k, _)| k == key)?;
+        let entry = inner.entries.remove(pos);
+        let data = entry.1.clone();
+        inner.entries.push(entry);
+        Some(data)
+    }
+
+    fn insert(&self, key: MediaCacheKey, data: Arc<Vec<u8>>) {
+        if data.len() > MAX_ENTRY_SIZE {
+            return;
+        }
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        // 同じパスの古いデータ（更新前のファイルのもの）は置き換える
+        let mut removed_size = 0;
+        inner.entries.retain(|(k, v)| {
+            let is_same = k.path == key.path && k.entry == key.entry && k.variant == key.variant;
+            if is_same {
+                removed_size += v.len();
+            }
+            !is_same
+        });
+        inner.total_size -= removed_size;
+        inner.total_size += data.len();
+        inner.entries.push((key, data));
+        while inner.total_size > MAX_CACHE_SIZE && !inner.entries.is_empty() {
+            let (_, evicted) = inner.entries.remove(0);
+            inner.total_size -= evicted.len();
+        }
+    }
+
+    /// ファイル（またはアーカイブ内のエントリ）の内容を取得する
+    /// キャッシュになければ読み込んでキャッシュに追加する
+    pub(crate) fn load(
+        &self,
+        path: &str,
+        entry: Option<&str>,
+        options: &ArchiveOptions,
+        archive_cache: &ArchiveCache,
+    ) -> Result<Arc<Vec<u8>>> {
+        let key = MediaCacheKey::new(path, entry, MediaVariant::Original);
+        if let Some(data) = self.get(&key) {
+            return Ok(data);
+        }
+        let data = match entry {
+            Some(entry) => {
+                archive_cache.with_archive(path, options, |archive| archive.read_entry(entry))?
+            }
+            None => std::fs::read(path).context("failed to read file")?,
+        };
+        let data = Arc::new(data);
+        self.insert(key, data.clone());
+        Ok(data)
+    }
+
+    /// 長辺を `max_size` 以下に縮小した表示用の画像を取得する
+    /// 縮小の必要がない画像や縮小できない画像は元のデータを返す
+    pub(crate) fn load_display(
+        &self,
+        path: &str,
+        entry: Option<&str>,
+        max_size: u32,
+        options: &ArchiveOptions,
+        archive_cache: &ArchiveCache,
+    ) -> Result<Arc<Vec<u8>>> {
+        self.display_size.fetch_max(max_size, Ordering::Relaxed);
+        let key = MediaCacheKey::new(path, entry, MediaVariant::Display(max_size));
+        self.load_converted(key, options, archive_cache, |original| {
+            create_display_image(original, max_size)
+        })
+    }
+
+    /// WebView で表示できる原寸の画像を取得する
+    /// WebView が表示できない形式（HEIC / JPEG XL など）は変換し、それ以外は元のデータを返す
+    pub(crate) fn load_viewable(
+        &self,
+        path: &str,
+        entry: Option<&str>,
+        options: &ArchiveOptions,
+        archive_cache: &ArchiveCache,
+    ) -> Result<Arc<Vec<u8>>> {
+        let key = MediaCacheKey::new(path, entry, MediaVariant::Viewable);
+        self.load_converted(key, options, archive_cache, create_viewable_image)
+    }
+
+    /// 元のデータを `convert` で変換したデータを取得する
+    /// 変換の必要がないデータや変換できないデータは元のデータを返す
+    fn load_converted(
+        &self,
+        key: MediaCacheKey,
+        options: &ArchiveOptions,
+        archive_cache: &ArchiveCache,
+        convert: impl FnOnce(&[u8]) -> Result<Option<Vec<u8>>>,
+    ) -> Result<Arc<Vec<u8>>> {
+        if let Some(data) = self.get(&key) {
+            return Ok(data);
+        }
+        let original = self.load(&key.path, key.entry.as_deref(), options, archive_cache)?;
+        match convert(&original) {
+            Ok(Some(data)) => {
+                let data = Arc::new(data);
+                self.insert(key, data.clone());
+                Ok(data)
+            }
+            // デコードできない形式はそのまま WebView に任せる
+            Ok(None) | Err(_) => Ok(original),
+        }
+    }
+
+    /// 変換・縮小した画像を破棄する（ICC プロファイルの扱いの設定が変わったときなど）
+    pub(crate) fn clear_converted(&self) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        inner
+            .entries
+            .retain(|(k, _)| k.variant == MediaVariant::Original);
+        inner.total_size = inner.entries.iter().map(|(_, v)| v.len()).sum();
+    }
+
+    /// 先読みで作る表示用の画像の大きさ（表示用の画像がまだ要求されていなければ None）
+    pub(crate) fn display_size(&self) -> Option<u32> {
+        Some(self.display_size.load(Ordering::Relaxed)).filter(|&size| size > 0)
+    }
+}
@@ -0,0 +1,442 @@
+//! 画像のメタデータ（EXIF / IPTC / XMP）の解析
+//!
+//! 撮影日時・カメラ・レンズ・露出・位置情報などを表示するため、画像に埋め込まれた
+//! メタデータを読み取る。EXIF は kamadak-exif で解析し、IPTC は JPEG の APP13
+//! (Photoshop IRB)、XMP はデータ中の `x:xmpmeta` パケットを探して取り出す
+
+use anyhow::Result;
+use exif::{In, Tag, Value};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::Serialize;
+use std::io::Cursor;
+
+use crate::utils::image_utils::{detect_mime_type, image_dimensions};
+
+/// 画像のメタデータ
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ImageMetadata {
+    /// EXIF の Orientation を適用した後の幅
+    pub width: u32,
+    /// EXIF の Orientation を適用した後の高さ
+    pub height: u32,
+    /// ファイルサイズ（アーカイブ内の画像は展開後のサイズ）
+    pub file_size: u64,
+    /// 内容から判定した形式の MIME タイプ
+    pub format: String,
+    pub exif: Option<ExifMetadata>,
+    pub iptc: Option<IptcMetadata>,
+    pub xmp: Option<XmpMetadata>,
+}
+
+/// メタデータの項目（表示用に整形した値）
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetadataField {
+    pub name: String,
+    pub value: String,
+}
+
+/// EXIF のうち主な項目と全項目
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExifMetadata {
+    /// 撮影日時（DateTimeOriginal、なければ DateTime）
+    pub date_time: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens_model: Option<String>,
+    /// 露出時間（"1/125 s" など）
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// 焦点距離 (mm)
+    pub focal_length: Option<f64>,
+    pub orientation: Option<u8>,
+    pub gps: Option<GpsPosition>,
+    /// すべての項目（サムネイル用の IFD とメーカーノートは除く）
+    pub fields: Vec<MetadataField>,
+}
+
+/// 撮影位置（度。南緯・西経は負の値）
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// 高度 (m。海面下は負の値)
+    pub altitude: Option<f64>,
+}
+
+/// IPTC (IIM) のうち主な項目と全項目
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IptcMetadata {
+    pub object_name: Option<String>,
+    pub headline: Option<String>,
+    pub caption: Option<String>,
+    pub keywords: Vec<String>,
+    pub by_line: Option<String>,
+    pub credit: Option<String>,
+    pub copyright: Option<String>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    /// Application Record (2:xx) のすべての項目
+    pub fields: Vec<MetadataField>,
+}
+
+/// XMP のパケットとプロパティ
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct XmpMetadata {
+    /// XMP パケットの XML
+    pub raw: String,
+    /// 接頭辞付きのプロパティ名と値（配列の値はカンマ区切りで連結する）
+    pub properties: Vec<MetadataField>,
+}
+
+/// 画像データのメタデータを読み取る（`name` は形式を内容から判定できない場合に拡張子で判定する）
+pub(crate) fn read_image_metadata(data: &[u8], name: &str) -> Result<ImageMetadata> {
+    let (width, height) = image_dimensions(data)?;
+    Ok(ImageMetadata {
+        width,
+        height,
+        file_size: data.len() as u64,
+        format: detect_mime_type(data, name).to_string(),
+        exif: read_exif(data),
+        iptc: find_iptc_block(data).map(parse_iptc),
+        xmp: find_xmp_packet(data).map(parse_xmp),
+    })
+}
+
+/// EXIF を読み取る（JPEG / TIFF / HEIF / PNG / WebP）
+fn read_exif(data: &[u8]) -> Option<ExifMetadata> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    let field = |tag: Tag| exif.get_field(tag, In::PRIMARY);
+    let text = |tag: Tag| match &field(tag)?.value {
+        Value::Ascii(values) => {
+            let text = String::from_utf8_lossy(values.first()?);
+            Some(text.trim_end_matches('\0').trim().to_string()).filter(|t| !t.is_empty())
+        }
+        _ => None,
+    };
+    let rational = |tag: Tag| match &field(tag)?.value {
+        Value::Rational(values) => values.first().map(|v| v.to_f64()),
+        _ => None,
+    };
+    let uint = |tag: Tag| field(tag)?.value.get_uint(0);
+
+    let fields = exif
+        .fields()
+        .filter(|f| f.ifd_num == In::PRIMARY && f.tag != Tag::MakerNote)
+        .map(|f| MetadataField {
+            name: f.tag.to_string(),
+            value: f.display_value().with_unit(&exif).to_string(),
+        })
+        .collect();
+
+    Some(ExifMetadata {
+        date_time: field(Tag::DateTimeOriginal)
+            .or_else(|| field(Tag::DateTime))
+            .map(|f| f.display_value().to_string()),
+        make: text(Tag::Make),
+        model: text(Tag::Model),
+        lens_model: text(Tag::LensModel),
+        exposure_time: field(Tag::ExposureTime)
+            .map(|f| f.display_value().with_unit(&exif).to_string()),
+        f_number: rational(Tag::FNumber),
+        iso: uint(Tag::PhotographicSensitivity),
+        focal_length: rational(Tag::FocalLength),
+        orientation: uint(Tag::Orientation).map(|v| v as u8),
+        gps: read_gps_position(&exif),
+        fields,
+    })
+}
+
+/// EXIF の GPS 情報から撮影位置を求める
+fn read_gps_position(exif: &exif::Exif) -> Option<GpsPosition> {
+    // 度・分・秒の 3 つの値を度に変換し、南緯・西経なら負にする
+    let coordinate = |tag: Tag, ref_tag: Tag, negative_ref: &str| {
+        let Value::Rational(values) = &exif.get_field(tag, In::PRIMARY)?.value else {
+            return None;
+        };
+        let [degrees, minutes, seconds] = values.as_slice() else {
+            return None;
+        };
+        let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+        let is_negative = exif
+            .get_field(ref_tag, In::PRIMARY)
+            .is_some_and(|f| f.display_value().to_string().starts_with(negative_ref));
+        Some(if is_negative { -value } else { value })
+    };
+    let altitude = match &exif.get_field(Tag::GPSAltitude, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().map(|v| v.to_f64()),
+        _ => None,
+    };
+    let below_sea_level = exif
+        .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        == Some(1);
+    Some(GpsPosition {
+        latitude: coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?,
+        longitude: coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?,
+        altitude: altitude.map(|a| if below_sea_level { -a } else { a }),
+    })
+}
+
+/// JPEG のセグメントをたどり、APP13 (Photoshop 3.0) に含まれる IPTC のブロックを探す
+fn find_iptc_block(data: &[u8]) -> Option<&[u8]> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // 埋め草の 0xFF は読み飛ばす
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // 画像データ (SOS) 以降にメタデータはない
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length.max(2))?;
+        if marker == 0xED {
+            let iptc = segment
+                .strip_prefix(b"Photoshop 3.0\0")
+                .and_then(find_irb_iptc);
+            if iptc.is_some() {
+                return iptc;
+            }
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Photoshop の画像リソースブロック (8BIM) から IPTC-NAA (0x0404) のデータを探す
+fn find_irb_iptc(mut irb: &[u8]) -> Option<&[u8]> {
+    while irb.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([*irb.get(4)?, *irb.get(5)?]);
+        // 名前は Pascal 文字列（長さのバイトを含めて偶数バイトに揃える）
+        let name_size = (*irb.get(6)? as usize + 2) & !1;
+        let size_pos = 6 + name_size;
+        let size = u32::from_be_bytes(irb.get(size_pos..size_pos + 4)?.try_into().ok()?) as usize;
+        let data_pos = size_pos + 4;
+        let block = irb.get(data_pos..data_pos + size)?;
+        if id == 0x0404 {
+            return Some(block);
+        }
+        irb = irb.get(data_pos + ((size + 1) & !1)..)?;
+    }
+    None
+}
+
+/// IPTC の Application Record (2:xx) の項目名
+fn iptc_dataset_name(dataset: u8) -> String {
+    match dataset {
+        5 => "ObjectName",
+        15 => "Category",
+        20 => "SupplementalCategory",
+        25 => "Keywords",
+        40 => "SpecialInstructions",
+        55 => "DateCreated",
+        60 => "TimeCreated",
+        80 => "By-line",
+        85 => "By-lineTitle",
+        90 => "City",
+        92 => "Sub-location",
+        95 => "Province-State",
+        101 => "Country-PrimaryLocationName",
+        105 => "Headline",
+        110 => "Credit",
+        115 => "Source",
+        116 => "CopyrightNotice",
+        120 => "Caption-Abstract",
+        122 => "Writer-Editor",
+        _ => return format!("2:{}", dataset),
+    }
+    .to_string()
+}
+
+/// IPTC の値の文字列を復号する（UTF-8 でなければ Shift_JIS とみなす）
+fn decode_iptc_value(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) => text.to_string(),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(value).0.into_owned(),
+    }
+}
+
+/// IPTC のデータセットを解析する
+fn parse_iptc(mut block: &[u8]) -> IptcMetadata {
+    let mut iptc = IptcMetadata::default();
+    while block.len() >= 5 && block[0] == 0x1C {
+        let (record, dataset) = (block[1], block[2]);
+        let size = u16::from_be_bytes([block[3], block[4]]) as usize;
+        // 32KB を超える拡張データセットは使われないため扱わない
+        if size & 0x8000 != 0 {
+            break;
+        }
+        let Some(value) = block.get(5..5 + size) else {
+            break;
+        };
+        block = &block[5 + size..];
+        if record != 2 || dataset == 0 {
+            continue;
+        }
+
+        let value = decode_iptc_value(value).trim().to_string();
+        match dataset {
+            5 => iptc.object_name = Some(value.clone()),
+            25 => iptc.keywords.push(value.clone()),
+            80 => iptc.by_line = Some(value.clone()),
+            90 => iptc.city = Some(value.clone()),
+            101 => iptc.country = Some(value.clone()),
+            105 => iptc.headline = Some(value.clone()),
+            110 => iptc.credit = Some(value.clone()),
+            116 => iptc.copyright = Some(value.clone()),
+            120 => iptc.caption = Some(value.clone()),
+            _ => {}
+        }
+        iptc.fields.push(MetadataField {
+            name: iptc_dataset_name(dataset),
+            value,
+        });
+    }
+    iptc
+}
+
+/// データ中の XMP パケット（`<x:xmpmeta ...>` 〜 `</x:xmpmeta>`）を探す
+fn find_xmp_packet(data: &[u8]) -> Option<&str> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+    let start = data.windows(START.len()).position(|w| w == START)?;
+    let length = data[start..].windows(END.len()).position(|w| w == END)? + END.len();
+    std::str::from_utf8(&data[start..start + length]).ok()
+}
+
+/// XMP のプロパティを取り出す
+/// rdf:Description の属性と、要素のテキスト（rdf:Seq / rdf:Bag / rdf:Alt の各要素は
+/// 親のプロパティの値として連結する）をプロパティとして扱う
+fn parse_xmp(xml: &str) -> XmpMetadata {
+    let mut properties: Vec<MetadataField> = vec![];
+    let mut add_property = |name: String, value: String| {
+        match properties.iter_mut().find(|p| p.name == name) {
+            Some(property) => {
+                property.value.push_str(", ");
+                property.value.push_str(&value);
+            }
+            None => properties.push(MetadataField { name, value }),
+        };
+    };
+
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    // 開いている要素の名前
+    let mut stack: Vec<String> = vec![];
+    loop {
+        match reader.read_event() {
+            Ok(Event::Empty(e)) => {
+                for (name, value) in description_attributes(&e) {
+                    add_property(name, value);
+                }
+            }
+            Ok(Event::Start(e)) => {
+                for (name, value) in description_attributes(&e) {
+                    add_property(name, value);
+                }
+                stack.push(String::from_utf8_lossy(e.name().as_ref()).to_string());
+            }
+            Ok(Event::End(_)) => {
+                stack.pop();
+            }
+            Ok(Event::Text(e)) => {
+                let property = stack
+                    .iter()
+                    .rev()
+                    .find(|name| !name.starts_with("rdf:") && !name.starts_with("x:"));
+                if let (Some(property), Ok(value)) = (property, e.unescape()) {
+                    add_property(property.clone(), value.to_string());
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    XmpMetadata {
+        raw: xml.to_string(),
+        properties,
+    }
+}
+
+/// rdf:Description の属性として書かれたプロパティ（名前空間の宣言と rdf:about は除く）
+fn description_attributes(element: &BytesStart) -> Vec<(String, String)> {
+    if element.name().as_ref() != b"rdf:Description" {
+        return vec![];
+    }
+    element
+        .attributes()
+        .flatten()
+        .filter_map(|attr| {
+            let name = String::from_utf8_lossy(attr.key.as_ref()).to_string();
+            if name.starts_with("xmlns") || name.starts_with("rdf:") {
+                return None;
+            }
+            Some((name, attr.unescape_value().ok()?.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iptc_in_jpeg_app13() {
+        let mut iptc = vec![];
+        for (dataset, value) in [(5u8, "タイトル"), (25, "sea"), (25, "sky")] {
+            iptc.extend([0x1C, 2, dataset]);
+            iptc.extend((value.len() as u16).to_be_bytes());
+            iptc.extend(value.as_bytes());
+        }
+        let mut app13 = b"Photoshop 3.0\0".to_vec();
+        app13.extend(b"8BIM\x04\x04\0\0");
+        app13.extend((iptc.len() as u32).to_be_bytes());
+        app13.extend(&iptc);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xED];
+        jpeg.extend((app13.len() as u16 + 2).to_be_bytes());
+        jpeg.extend(&app13);
+        jpeg.extend([0xFF, 0xDA]);
+
+        let iptc = parse_iptc(find_iptc_block(&jpeg).unwrap());
+        assert_eq!(iptc.object_name.as_deref(), Some("タイトル"));
+        assert_eq!(iptc.keywords, vec!["sea", "sky"]);
+        assert_eq!(iptc.fields.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_xmp_properties() {
+        let data = br#"....<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="4"/>
+<rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:subject><rdf:Bag><rdf:li>sea</rdf:li><rdf:li>sky</rdf:li></rdf:Bag></dc:subject>
+</rdf:Description></rdf:RDF></x:xmpmeta>...."#;
+        let xmp = parse_xmp(find_xmp_packet(data).unwrap());
+        assert_eq!(
+            xmp.properties,
+            vec![
+                MetadataField {
+                    name: "xmp:Rating".to_string(),
+                    value: "4".to_string()
+                },
+                MetadataField {
+                    name: "dc:subject".to_string(),
+                    value: "sea, sky".to_string()
+                },
+            ]
+        );
+    }
+}
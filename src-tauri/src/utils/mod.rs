@@ -1,3 +1,13 @@
+pub mod animation_utils;
+pub mod archive;
+pub mod color_utils;
 pub mod file_utils;
+pub mod format_registry;
+pub mod image_utils;
+pub mod media_cache;
+pub mod metadata_utils;
+pub mod raw_utils;
+pub mod svg_utils;
 pub mod thumbnail_utils;
+pub mod tile_utils;
 pub mod watcher_utils;
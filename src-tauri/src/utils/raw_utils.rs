@@ -0,0 +1,75 @@
+//! カメラの RAW 画像のデコード
+//!
+//! RAW の現像は重いため、表示やサムネイルには RAW ファイルに埋め込まれたプレビュー JPEG
+//! （多くのカメラは原寸のものを持つ）を使う。プレビューがない場合だけ簡易的に現像する。
+//! プレビューも現像結果も撮影時の向きのままなので、EXIF の Orientation に従って回転する
+
+use anyhow::{anyhow, Context, Result};
+use image::metadata::Orientation;
+use image::DynamicImage;
+use rawler::decoders::{Decoder, RawDecodeParams};
+use rawler::imgop::develop::RawDevelop;
+use rawler::rawsource::RawSource;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// RAW 画像として読み込めるデータか（対応するカメラの RAW ファイルか）
+pub(crate) fn is_raw_image(data: &[u8]) -> bool {
+    rawler::get_decoder(&RawSource::new_from_slice(data)).is_ok()
+}
+
+/// RAW 画像をデコードする
+/// 埋め込みのプレビューを優先し、なければ RAW データを現像する
+pub(crate) fn decode_raw_image(data: &[u8]) -> Result<DynamicImage> {
+    let source = RawSource::new_from_slice(data);
+    let decoder = rawler::get_decoder(&source).context("unsupported raw image")?;
+    let params = RawDecodeParams::default();
+
+    let preview = decoder
+        .full_image(&source, &params)
+        .ok()
+        .flatten()
+        .or_else(|| decoder.preview_image(&source, &params).ok().flatten());
+    let mut image = match preview {
+        Some(image) => image,
+        None => develop_raw_image(&source, &params)?,
+    };
+
+    if let Some(orientation) = raw_orientation(decoder.as_ref(), &source, &params) {
+        image.apply_orientation(orientation);
+    }
+    Ok(image)
+}
+
+/// RAW 画像の EXIF の Orientation を取得する
+pub(crate) fn read_raw_orientation(data: &[u8]) -> Option<Orientation> {
+    let source = RawSource::new_from_slice(data);
+    let decoder = rawler::get_decoder(&source).ok()?;
+    raw_orientation(decoder.as_ref(), &source, &RawDecodeParams::default())
+}
+
+fn raw_orientation(
+    decoder: &dyn Decoder,
+    source: &RawSource,
+    params: &RawDecodeParams,
+) -> Option<Orientation> {
+    decoder
+        .raw_metadata(source, params)
+        .ok()
+        .and_then(|metadata| metadata.exif.orientation)
+        .and_then(|orientation| Orientation::from_exif(orientation as u8))
+}
+
+/// RAW データを既定の設定で現像する
+/// 未対応のセンサー配列などで現像処理がパニックすることがあるため、エラーとして扱う
+fn develop_raw_image(source: &RawSource, params: &RawDecodeParams) -> Result<DynamicImage> {
+    let raw = rawler::decode(source, params).context("failed to decode raw image")?;
+    catch_unwind(AssertUnwindSafe(|| {
+        RawDevelop::default()
+            .develop_intermediate(&raw)
+            .ok()
+            .and_then(|intermediate| intermediate.to_dynamic_image())
+    }))
+    .ok()
+    .flatten()
+    .ok_or_else(|| anyhow!("failed to develop raw image"))
+}
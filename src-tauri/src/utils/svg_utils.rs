@@ -0,0 +1,83 @@
+//! SVG のラスタライズ
+//!
+//! WebView は SVG をそのまま表示できるが、サムネイル・埋め込み・表示用の縮小画像には
+//! ピクセルの画像が必要になる。resvg で指定した大きさに描画する
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, RgbaImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{fontdb, Options, Tree};
+use std::sync::{Arc, OnceLock};
+
+/// SVG のデータか（先頭の XML 宣言・コメントの後に `<svg` 要素があるか）
+pub(crate) fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(4096)];
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<!--") || head.starts_with("<svg"))
+        && head.contains("<svg")
+}
+
+/// SVG 内のテキストの描画に使うシステムフォント（読み込みに時間がかかるため一度だけ読み込む）
+fn font_database() -> Arc<fontdb::Database> {
+    static FONT_DATABASE: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    FONT_DATABASE
+        .get_or_init(|| {
+            let mut database = fontdb::Database::new();
+            database.load_system_fonts();
+            Arc::new(database)
+        })
+        .clone()
+}
+
+fn parse_svg(data: &[u8]) -> Result<Tree> {
+    let options = Options {
+        fontdb: font_database(),
+        ..Default::default()
+    };
+    Tree::from_data(data, &options).context("failed to parse svg")
+}
+
+/// 長辺を `size` にしたときの SVG の大きさ
+fn scaled_size(tree: &Tree, size: u32) -> (u32, u32, f32) {
+    let (width, height) = (tree.size().width(), tree.size().height());
+    let scale = size as f32 / width.max(height);
+    (
+        ((width * scale).round() as u32).max(1),
+        ((height * scale).round() as u32).max(1),
+        scale,
+    )
+}
+
+/// 長辺を `size` にしたときの SVG の大きさを取得する
+pub(crate) fn svg_dimensions(data: &[u8], size: u32) -> Result<(u32, u32)> {
+    let (width, height, _) = scaled_size(&parse_svg(data)?, size);
+    Ok((width, height))
+}
+
+/// SVG を長辺が `size` になるように拡大・縮小して描画する
+pub(crate) fn render_svg(data: &[u8], size: u32) -> Result<DynamicImage> {
+    let tree = parse_svg(data)?;
+    let (width, height, scale) = scaled_size(&tree, size);
+    let mut pixmap = Pixmap::new(width, height)
+        .ok_or_else(|| anyhow!("invalid svg size: {}x{}", width, height))?;
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia のピクセルは乗算済みアルファのため、通常のアルファに戻す
+    let mut pixels = pixmap.take();
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha > 0 && alpha < 255 {
+            for c in &mut pixel[..3] {
+                *c = ((*c as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    }
+    let image = RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("failed to render svg"))?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
@@ -9,6 +9,8 @@ use sha2::{Digest, Sha256};
 use std::io::Cursor;
 use std::path::Path;
 
+use crate::utils::image_utils::decode_image_file;
+
 /// サムネイル生成結果
 pub struct ThumbnailData {
     /// JPEG エンコードされたサムネイル画像データ
@@ -20,12 +22,16 @@ pub struct ThumbnailData {
 /// サムネイルのサイズ（幅・高さ）
 pub const THUMBNAIL_SIZE: u32 = 224;
 
+/// サムネイルの作り方のバージョン（作り方を変えたらインクリメントし、保存済みのサムネイルを作り直させる）
+/// 1: 初版 / 2: EXIF の向きの適用と ICC プロファイルによる sRGB への変換
+pub const THUMBNAIL_VERSION: i32 = 2;
+
 /// 画像ファイルから 224×224 のサムネイルを生成する
 pub fn generate_thumbnail_data(image_path: &str) -> Result<ThumbnailData> {
     let path = Path::new(image_path);
 
-    // 画像を読み込み
-    let img = image::open(path)?;
+    // 画像を読み込み（HEIC / JPEG XL なども含む）
+    let img = decode_image_file(path)?;
 
     // 224×224 にリサイズ（アスペクト比を維持し、余白を黒で埋める）
     let resized = resize_with_padding(&img, THUMBNAIL_SIZE, THUMBNAIL_SIZE);
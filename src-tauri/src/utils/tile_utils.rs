@@ -0,0 +1,282 @@
+//! 巨大な画像を拡大表示するためのタイル分割
+//!
+//! 地図や大判スキャンは表示用に縮小した画像では拡大時に細部が失われる。画像を解像度ごとの
+//! レベル（Deep Zoom と同じく、最大レベルが原寸で 1 レベル下がるごとに半分）に分け、
+//! 各レベルを `TILE_SIZE` 四方のタイルに分割して、表示に必要なタイルだけを返す
+//!
+//! タイルは要求されたときに元の画像から作成し、アプリのデータディレクトリにキャッシュする。
+//! 作成中は元の画像を 1 枚だけメモリに保持し、続くタイルの要求でデコードし直さないようにする
+//! デコード中に待つのは同じ画像のタイルの要求だけで、別の画像の要求は待たせない
+
+use anyhow::{anyhow, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::utils::archive::root_archive_path;
+use crate::utils::image_utils::{decode_image, image_dimensions, image_orientation};
+
+/// タイルの幅・高さ
+pub const TILE_SIZE: u32 = 256;
+/// タイル JPEG の品質
+const TILE_JPEG_QUALITY: u8 = 85;
+/// タイルをキャッシュする画像の最大数（超えたら古い画像のタイルから削除する）
+const MAX_TILED_IMAGES: usize = 32;
+
+/// タイル分割の情報
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TileInfo {
+    /// 原寸の幅
+    pub width: u32,
+    /// 原寸の高さ
+    pub height: u32,
+    pub tile_size: u32,
+    /// 原寸のレベル（レベル 0 は 1×1 ピクセル）
+    pub max_level: u32,
+    /// 元の画像の EXIF の Orientation (1〜8)
+    /// 幅・高さとタイルは回転・反転を適用した後のもの
+    pub orientation: u8,
+}
+
+impl TileInfo {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        let mut max_level = 0;
+        while (1u64 << max_level) < width.max(height) as u64 {
+            max_level += 1;
+        }
+        Self {
+            width,
+            height,
+            tile_size: TILE_SIZE,
+            max_level,
+            orientation: 1,
+        }
+    }
+
+    /// 指定したレベルの画像の大きさ
+    pub(crate) fn level_size(&self, level: u32) -> (u32, u32) {
+        let scale = 1u64 << (self.max_level - level.min(self.max_level));
+        (
+            (self.width as u64).div_ceil(scale) as u32,
+            (self.height as u64).div_ceil(scale) as u32,
+        )
+    }
+}
+
+/// 画像の範囲外のタイルが要求されたときのエラー
+/// 読み込みの失敗と区別して、リクエストの誤りとして応答する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TileRangeError {
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl fmt::Display for TileRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tile out of range: {}/{}/{}", self.level, self.x, self.y)
+    }
+}
+
+impl std::error::Error for TileRangeError {}
+
+/// 画像データのタイル分割の情報を取得する（ヘッダのみ読み込む）
+pub(crate) fn read_tile_info(data: &[u8]) -> Result<TileInfo> {
+    let (width, height) = image_dimensions(data)?;
+    Ok(TileInfo {
+        orientation: image_orientation(data)?.to_exif(),
+        ..TileInfo::new(width, height)
+    })
+}
+
+/// 画像を識別するキー（パス + エントリ名 + 実ファイルのサイズと更新日時）
+/// タイルやアニメーションのフレームのキャッシュに使う
+pub(crate) fn image_source_key(path: &str, entry: Option<&str>) -> String {
+    let metadata = std::fs::metadata(root_archive_path(path)).ok();
+    let source = format!(
+        "{}|{}|{:?}|{:?}",
+        path,
+        entry.unwrap_or_default(),
+        metadata.as_ref().map(|m| m.len()),
+        metadata.and_then(|m| m.modified().ok()),
+    );
+    format!("{:x}", Sha256::digest(source.as_bytes()))[..32].to_string()
+}
+
+/// デコード済みの画像の置き場所
+/// デコード中はこの置き場所だけをロックし、同じ画像の要求だけを待たせる
+type SourceSlot = Arc<Mutex<Option<Arc<DynamicImage>>>>;
+
+/// タイルのディスクキャッシュ
+pub struct TileCache {
+    /// キャッシュの保存先（画像ごとのサブディレクトリにタイルを保存する）
+    dir: PathBuf,
+    /// 直近にタイルを作成した画像 (キー, デコード済みの画像の置き場所)
+    source: Mutex<Option<(String, SourceSlot)>>,
+}
+
+impl TileCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            source: Mutex::new(None),
+        }
+    }
+
+    /// タイルの JPEG データを取得する
+    /// キャッシュになければ `load_source` で元の画像を読み込んで作成する
+    pub(crate) fn get_tile(
+        &self,
+        key: &str,
+        level: u32,
+        x: u32,
+        y: u32,
+        load_source: impl FnOnce() -> Result<Arc<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        let image_dir = self.dir.join(key);
+        let tile_path = image_dir.join(format!("{}_{}_{}.jpg", level, x, y));
+        if let Ok(data) = std::fs::read(&tile_path) {
+            return Ok(data);
+        }
+
+        let source = self.get_source(key, load_source)?;
+        let data = create_tile(&source, level, x, y)?;
+
+        if !image_dir.exists() {
+            std::fs::create_dir_all(&image_dir)?;
+            self.evict_old_images();
+        }
+        let part_path = image_dir.join(format!("{}_{}_{}.part", level, x, y));
+        std::fs::write(&part_path, &data)?;
+        std::fs::rename(&part_path, &tile_path)?;
+        Ok(data)
+    }
+
+    /// デコード済みの元の画像を取得する
+    /// デコード中は画像の置き場所のロックを保持し、同じ画像の並行したタイル要求でデコードが重複しないようにする
+    fn get_source(
+        &self,
+        key: &str,
+        load_source: impl FnOnce() -> Result<Arc<Vec<u8>>>,
+    ) -> Result<Arc<DynamicImage>> {
+        let slot = {
+            let mut source = self.source.lock().map_err(|e| anyhow!("{}", e))?;
+            match source.as_ref() {
+                Some((source_key, slot)) if source_key == key => slot.clone(),
+                // 古い画像の置き場所を手放し、使い終わったら解放されるようにする
+                _ => {
+                    let slot = SourceSlot::default();
+                    *source = Some((key.to_string(), slot.clone()));
+                    slot
+                }
+            }
+        };
+        let mut image = slot.lock().map_err(|e| anyhow!("{}", e))?;
+        if let Some(image) = image.as_ref() {
+            return Ok(image.clone());
+        }
+        let decoded = Arc::new(decode_image(&load_source()?)?);
+        *image = Some(decoded.clone());
+        Ok(decoded)
+    }
+
+    /// キャッシュしたタイルをすべて削除する（ICC プロファイルの扱いの設定が変わったときなど）
+    pub(crate) fn clear(&self) {
+        if let Ok(mut source) = self.source.lock() {
+            *source = None;
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+
+    /// タイルをキャッシュしている画像が上限を超えたら、更新日時の古いものから削除する
+    fn evict_old_images(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut dirs: Vec<_> = entries
+            .flatten()
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .collect();
+        if dirs.len() <= MAX_TILED_IMAGES {
+            return;
+        }
+        dirs.sort();
+        for (_, path) in &dirs[..dirs.len() - MAX_TILED_IMAGES] {
+            let _ = std::fs::remove_dir_all(path);
+        }
+    }
+}
+
+/// 元の画像からタイルを切り出し、レベルの解像度に縮小して JPEG にエンコードする
+/// 透過は JPEG で表現できないため失われる
+fn create_tile(source: &DynamicImage, level: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+    let (width, height) = source.dimensions();
+    let info = TileInfo::new(width, height);
+    let out_of_range = TileRangeError { level, x, y };
+    if level > info.max_level {
+        return Err(out_of_range.into());
+    }
+    let (level_width, level_height) = info.level_size(level);
+    // 座標はリクエストの値そのままのため、オーバーフローしないよう u64 で計算する
+    let (left, top) = (x as u64 * TILE_SIZE as u64, y as u64 * TILE_SIZE as u64);
+    if left >= level_width as u64 || top >= level_height as u64 {
+        return Err(out_of_range.into());
+    }
+    let tile_width = TILE_SIZE.min(level_width - left as u32);
+    let tile_height = TILE_SIZE.min(level_height - top as u32);
+
+    // タイルの範囲を原寸の座標に変換して切り出す
+    let scale = 1u64 << (info.max_level - level);
+    let source_x = (left * scale).min(width as u64 - 1) as u32;
+    let source_y = (top * scale).min(height as u64 - 1) as u32;
+    let source_width = ((tile_width as u64 * scale) as u32).min(width - source_x);
+    let source_height = ((tile_height as u64 * scale) as u32).min(height - source_y);
+    let mut tile = source.crop_imm(source_x, source_y, source_width, source_height);
+    if scale > 1 {
+        tile = tile.resize_exact(tile_width, tile_height, FilterType::Triangle);
+    }
+
+    let mut buf = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut buf, TILE_JPEG_QUALITY);
+    tile.to_rgb8().write_with_encoder(encoder)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_levels() {
+        let info = TileInfo::new(20000, 15000);
+        assert_eq!(info.max_level, 15);
+        assert_eq!(info.level_size(15), (20000, 15000));
+        assert_eq!(info.level_size(14), (10000, 7500));
+        assert_eq!(info.level_size(8), (157, 118));
+        assert_eq!(info.level_size(0), (1, 1));
+        assert_eq!(TileInfo::new(1, 1).max_level, 0);
+    }
+
+    #[test]
+    fn test_create_tile_out_of_range() {
+        let source = DynamicImage::new_rgb8(1000, 600);
+        assert!(create_tile(&source, 10, 3, 2).is_ok());
+        for (level, x, y) in [
+            (10, 4, 0),
+            (11, 0, 0),
+            (10, u32::MAX, 0),
+            (10, 0, u32::MAX / 2),
+        ] {
+            let e = create_tile(&source, level, x, y).unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<TileRangeError>(),
+                Some(&TileRangeError { level, x, y })
+            );
+        }
+    }
+}
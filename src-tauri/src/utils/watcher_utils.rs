@@ -5,6 +5,7 @@ use tauri::{AppHandle, Emitter, State};
 
 use crate::service::app_state::AppState;
 use crate::service::explorer_state::CachedDirEntry;
+use crate::utils::archive::ArchiveCache;
 
 /// ディレクトリ監視を開始するヘルパー関数
 ///
@@ -106,9 +107,14 @@ pub fn create_explorer_watcher_callback(
 pub fn create_viewer_watcher_callback(
     app: AppHandle,
     path: String,
+    archive_cache: Arc<ArchiveCache>,
 ) -> impl Fn(NotifyResult<Event>) + Send + 'static {
     move |res| match res {
-        Ok(_) => {
+        Ok(event) => {
+            // 変更されたアーカイブのハンドルを破棄して、次回の読み込みで開き直させる
+            for changed in &event.paths {
+                archive_cache.invalidate(&changed.to_string_lossy());
+            }
             app.emit("directory-tree-changed", &path)
                 .unwrap_or_default();
         }
@@ -0,0 +1,87 @@
+import { invoke } from '@tauri-apps/api/core';
+import { createSignal, onMount } from 'solid-js';
+
+// バックエンドの format_registry::FormatSettings
+type FormatSettingsValue = {
+  image_extensions: string[];
+  video_extensions: string[];
+};
+
+type Props = {
+  onClose: () => void;
+};
+
+// カンマ・空白区切りの入力を拡張子の一覧にする（先頭の "." や大文字小文字はバックエンドでそろえる）
+const parseExtensions = (value: string) =>
+  value.split(/[\s,]+/).filter((ext) => ext !== '');
+
+// 画像・動画として扱う拡張子を追加する設定画面
+// 設定は次に開くフォルダ・アーカイブから反映される
+export const FormatSettings = (props: Props) => {
+  const [imageExtensions, setImageExtensions] = createSignal('');
+  const [videoExtensions, setVideoExtensions] = createSignal('');
+
+  onMount(async () => {
+    const settings = await invoke<FormatSettingsValue>('get_format_settings');
+    setImageExtensions(settings.image_extensions.join(', '));
+    setVideoExtensions(settings.video_extensions.join(', '));
+  });
+
+  const save = async () => {
+    const settings: FormatSettingsValue = {
+      image_extensions: parseExtensions(imageExtensions()),
+      video_extensions: parseExtensions(videoExtensions()),
+    };
+    await invoke('set_format_settings', { settings });
+    props.onClose();
+  };
+
+  return (
+    <div
+      class="fixed inset-0 z-50 flex items-center justify-center bg-black/50"
+      onClick={() => props.onClose()}
+    >
+      <div
+        class="flex min-w-96 flex-col gap-3 rounded-lg bg-neutral-800 p-4 text-sm shadow-lg"
+        onClick={(e) => e.stopPropagation()}
+      >
+        <div class="font-medium">追加の拡張子</div>
+        <label class="flex flex-col gap-1">
+          <span class="text-neutral-400">画像として扱う拡張子</span>
+          <input
+            class="rounded border border-neutral-600 bg-neutral-900 px-2 py-1"
+            placeholder="jpg_large, jfif2"
+            value={imageExtensions()}
+            onInput={(e) => setImageExtensions(e.currentTarget.value)}
+          />
+        </label>
+        <label class="flex flex-col gap-1">
+          <span class="text-neutral-400">動画として扱う拡張子</span>
+          <input
+            class="rounded border border-neutral-600 bg-neutral-900 px-2 py-1"
+            placeholder="ts, m4v"
+            value={videoExtensions()}
+            onInput={(e) => setVideoExtensions(e.currentTarget.value)}
+          />
+        </label>
+        <div class="text-xs text-neutral-500">
+          次に開くフォルダ・アーカイブから反映されます
+        </div>
+        <div class="flex justify-end gap-2">
+          <button
+            class="rounded px-3 py-1 transition-colors hover:bg-neutral-700"
+            onClick={() => props.onClose()}
+          >
+            キャンセル
+          </button>
+          <button
+            class="rounded bg-neutral-600 px-3 py-1 transition-colors hover:bg-neutral-500"
+            onClick={save}
+          >
+            保存
+          </button>
+        </div>
+      </div>
+    </div>
+  );
+};
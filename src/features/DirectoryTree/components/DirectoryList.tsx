@@ -12,6 +12,7 @@ type Props = {
   viewing?: File;
   tree: FileTree[];
   onClick: (path: File) => void;
+  onExpand: (path: string) => void;
 };
 
 export const DirectoryList: Component<Props> = (props) => {
@@ -28,6 +29,7 @@ export const DirectoryList: Component<Props> = (props) => {
                 tree={nd.Directory}
                 viewing={props.viewing}
                 onClick={props.onClick}
+                onExpand={props.onExpand}
               />
             ))
             .with({ File: { file_type: 'Image' } }, (nd) => (
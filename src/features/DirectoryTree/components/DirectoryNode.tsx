@@ -18,6 +18,7 @@ type Props = {
   tree: Directory;
   viewing?: File;
   onClick: (path: File) => void;
+  onExpand: (path: string) => void;
 };
 
 export const DirectoryNode: Component<Props> = (props) => {
@@ -25,9 +26,14 @@ export const DirectoryNode: Component<Props> = (props) => {
   const tree = createMemo(() => props.tree, undefined, {
     equals: equal,
   });
+  const toggle = () => {
+    // アーカイブ内のアーカイブは初めて開いたときに中身を読み込む
+    if (!open() && props.tree.lazy) props.onExpand(props.tree.path);
+    setOpen((prev) => !prev);
+  };
   return (
     <div class="w-full">
-      <NodeBaseStyle onClick={() => setOpen((prev) => !prev)}>
+      <NodeBaseStyle onClick={toggle}>
         <Show when={open()} fallback={<FaSolidCaretRight />}>
           <FaSolidCaretDown />
         </Show>
@@ -46,6 +52,7 @@ export const DirectoryNode: Component<Props> = (props) => {
                     tree={nd.Directory}
                     viewing={props.viewing}
                     onClick={props.onClick}
+                    onExpand={props.onExpand}
                   />
                 ))
                 .with({ File: { file_type: 'Image' } }, (nd) => (
@@ -31,7 +31,8 @@ export const ZipNode: Component<Props> = (props) => {
       onClick={() => props.onClick && props.onClick()}
     >
       <FaSolidImage />
-      <div class="hidden lg:block">{props.node.name}</div>
+      {/* name はアーカイブ内のフルパスなので、表示はファイル名部分のみ */}
+      <div class="hidden lg:block">{props.node.name.split('/').pop()}</div>
     </NodeBaseStyle>
   );
 };
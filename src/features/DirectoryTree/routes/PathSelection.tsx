@@ -6,6 +6,7 @@ type Props = {
   tree: FileTree[];
   viewing?: File;
   onSelectedChanged: (file: File) => void;
+  onExpand: (path: string) => void;
 };
 
 export const PathSelection: Component<Props> = (props) => {
@@ -16,6 +17,7 @@ export const PathSelection: Component<Props> = (props) => {
           viewing={props.viewing}
           tree={props.tree}
           onClick={(path) => props.onSelectedChanged(path)}
+          onExpand={props.onExpand}
         />
       </div>
     </div>
@@ -5,6 +5,10 @@ import fallback from '../../../assets/noimage.png';
 import { FaSolidCheck } from 'solid-icons/fa';
 import { convertFileSrc } from '@tauri-apps/api/core';
 
+// サムネイルはカスタム URI スキーム (siv://) から縮小した画像を読み込む
+// WebView が表示できない形式 (HEIC / JPEG XL など) もバックエンドで変換される
+const THUMBNAIL_MAX_SIZE = 512;
+
 type Props = {
   thumb: Thumbnail;
   showMarkAsRead: boolean;
@@ -18,7 +22,9 @@ export const Folder: Component<Props> = (props) => {
   const [data] = createResource(
     () => props.thumb.thumbpath,
     () =>
-      props.thumb.thumbpath ? convertFileSrc(props.thumb.thumbpath) : fallback,
+      props.thumb.thumbpath
+        ? `${convertFileSrc(props.thumb.thumbpath, 'siv')}?max=${THUMBNAIL_MAX_SIZE}`
+        : fallback,
   );
   return (
     <div
@@ -48,7 +54,9 @@ export const Folder: Component<Props> = (props) => {
         loading="lazy"
         onError={(e) => (e.currentTarget.src = fallback)}
       />
-      <div class="whitespace-nowrap text-ellipsis">{props.thumb.filename}</div>
+      <div class="whitespace-nowrap text-ellipsis" title={props.thumb.filename}>
+        {props.thumb.metadata?.display_title ?? props.thumb.filename}
+      </div>
     </div>
   );
 };
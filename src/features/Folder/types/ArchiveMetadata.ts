@@ -0,0 +1,11 @@
+/** アーカイブのメタデータ (ComicInfo.xml) */
+export type ArchiveMetadata = {
+  display_title?: string;
+  title?: string;
+  series?: string;
+  number?: string;
+  volume?: number;
+  writer?: string;
+  summary?: string;
+  right_to_left: boolean;
+};
@@ -1,7 +1,12 @@
+import type { ArchiveMetadata } from './ArchiveMetadata';
+
 export type Thumbnail = {
   path: string;
   filename: string;
   thumbpath: string;
   modified_at?: number;
   created_at?: number;
+  /** フォルダではなくアーカイブファイル (ZIP / CBZ など) のエントリか */
+  is_archive?: boolean;
+  metadata?: ArchiveMetadata;
 };
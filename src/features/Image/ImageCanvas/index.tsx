@@ -1,7 +1,7 @@
 import { convertFileSrc, invoke } from '@tauri-apps/api/core';
 import {
   createEffect,
-  createResource,
+  createMemo,
   createSignal,
   Match,
   on,
@@ -20,6 +20,8 @@ import '@videojs/themes/dist/fantasy/index.css';
 
 type Props = {
   viewing?: File;
+  // 前後の画像（ページ送りの前にデコードしておく）
+  neighbors: File[];
   moveForward: () => void;
   moveBackward: () => void;
 };
@@ -29,6 +31,8 @@ export const ImageCanvas: Component<Props> = (props) => {
   const [initialPosition, setInitialPosition] = createSignal({ x: 0, y: 0 });
   const [imageScale, setImageScale] = createSignal<number>(1);
   const [position, setPosition] = createSignal({ x: 0, y: 0 });
+  // 元の解像度の画像に切り替えたファイルのキー（一度拡大したらファイルが変わるまで縮小画像に戻さない）
+  const [originalKey, setOriginalKey] = createSignal<string | undefined>();
   let videoRef: HTMLVideoElement | undefined;
 
   const handleWheel = (e: WheelEvent) => {
@@ -71,15 +75,87 @@ export const ImageCanvas: Component<Props> = (props) => {
   const resetStatus = () => {
     setImageScale(1);
     setPosition({ x: 0, y: 0 });
+    setOriginalKey(undefined);
   };
 
-  const convertToLocalPath = async (file: File) => convertFileSrc(file.path);
+  // 拡大表示したら元の解像度の画像に切り替える
+  // 縮小し直しても切り替えたままにして、倍率 1 をまたぐたびに読み込み直さないようにする
+  // ファイルを変えたときは新しいファイルの縮小画像から読み込むよう、切り替えたファイルを覚えておく
+  createEffect(
+    on(imageScale, (scale) => {
+      if (scale > 1) setOriginalKey(props.viewing?.key);
+    }),
+  );
+  const useOriginal = () =>
+    props.viewing !== undefined && originalKey() === props.viewing.key;
+
+  // ファイルやアーカイブ内の画像・動画はカスタム URI スキーム (siv://) から生データのまま読み込む
+  // 動画のシークは Range リクエストで必要な範囲だけを読み込む
+  const MEDIA_PROTOCOL = 'siv';
+  const [retryCount, setRetryCount] = createSignal(0);
+
+  // 縮小・変換した画像はバックエンドで EXIF の向きを適用済みで、元の画像は WebView が
+  // EXIF の向きを適用する（image-orientation: from-image）ため、どちらも同じ向きで表示される
+
+  // 表示用に縮小した画像の長辺（画面の物理解像度に合わせる）
+  const DISPLAY_MAX_SIZE = Math.ceil(
+    Math.max(window.screen.width, window.screen.height) *
+      window.devicePixelRatio,
+  );
+
+  const buildMediaUrl = (file: File, useDisplaySize: boolean) => {
+    const url = convertFileSrc(file.path, MEDIA_PROTOCOL);
+    const params = new URLSearchParams();
+    if (file.file_type === 'Zip') params.set('entry', file.name);
+    if (useDisplaySize) params.set('max', String(DISPLAY_MAX_SIZE));
+    // パスワード入力後は URL を変えて読み込み直す
+    if (retryCount() > 0) params.set('retry', String(retryCount()));
+    const query = params.toString();
+    return query ? `${url}?${query}` : url;
+  };
+
+  // 拡大表示するまでは表示用に縮小した画像を読み込む
+  const convertToMediaUrl = (file: File) =>
+    buildMediaUrl(file, !isVideo() && !useOriginal());
 
-  const readImageInZip = async (file: File) => {
-    return invoke<string>('read_image_in_zip', {
-      path: file.path,
-      filename: file.name,
-    });
+  // 前後の画像を表示用の大きさで読み込んでデコードしておき、ページ送りですぐに表示できるようにする
+  // 参照を保持している間は WebView がデコード済みの画像を保持する
+  const preloadedImages: HTMLImageElement[] = [];
+  createEffect(
+    on(
+      () => props.neighbors,
+      (neighbors) => {
+        const images = neighbors.map((file) => {
+          const image = new Image();
+          image.src = buildMediaUrl(file, true);
+          image.decode().catch(() => {});
+          return image;
+        });
+        preloadedImages.splice(0, preloadedImages.length, ...images);
+      },
+    ),
+  );
+
+  // パスワード関連のエラーはバックエンドから "<接頭辞>: <アーカイブのパス>" の形で返される
+  const PASSWORD_ERROR_PATTERN = /^(password required|invalid password): (.*)$/;
+
+  // <img> / <video> ではエラーの内容がわからないため、読み込みに失敗したら取得し直して原因を確認する
+  const handleZipMediaError = async () => {
+    const url = data();
+    if (!url) return;
+    const response = await fetch(url, { headers: { Range: 'bytes=0-0' } });
+    if (response.status !== 401) return;
+    const matched = PASSWORD_ERROR_PATTERN.exec(await response.text());
+    if (!matched) return;
+    const [, reason, archivePath] = matched;
+    const message =
+      reason === 'invalid password'
+        ? `パスワードが違います。再入力してください\n${archivePath}`
+        : `パスワードを入力してください\n${archivePath}`;
+    const password = window.prompt(message);
+    if (password === null) return;
+    await invoke('set_archive_password', { path: archivePath, password });
+    setRetryCount((prev) => prev + 1);
   };
 
   const handleMouseDown = (event: MouseEvent) => {
@@ -106,18 +182,15 @@ export const ImageCanvas: Component<Props> = (props) => {
     }
   };
 
-  const [data] = createResource(
-    () => props.viewing,
-    () => {
-      const { viewing } = props;
-      if (!viewing) return '';
-      return match(viewing.file_type)
-        .with('Image', () => convertToLocalPath(viewing))
-        .with('Video', () => convertToLocalPath(viewing))
-        .with('Zip', () => readImageInZip(viewing))
-        .otherwise(() => '');
-    },
-  );
+  const isVideo = () => props.viewing?.is_video ?? false;
+
+  const data = createMemo(() => {
+    const { viewing } = props;
+    if (!viewing) return '';
+    return match(viewing.file_type)
+      .with('Image', 'Video', 'Zip', () => convertToMediaUrl(viewing))
+      .otherwise(() => '');
+  });
 
   createEffect(
     on(
@@ -130,7 +203,7 @@ export const ImageCanvas: Component<Props> = (props) => {
   // 動画ソース変更時に前の動画リソースを解放し、新しいソースを読み込む
   createEffect(
     on(data, () => {
-      if (videoRef && props.viewing?.file_type === 'Video') {
+      if (videoRef && isVideo()) {
         videoRef.load();
       }
     }),
@@ -164,22 +237,25 @@ export const ImageCanvas: Component<Props> = (props) => {
                   position: 'absolute',
                   left: '0',
                   top: '0',
+                  'image-orientation': 'from-image',
                 }}
               />
             </Match>
-            <Match when={props.viewing?.file_type === 'Video'}>
+            <Match when={isVideo()}>
               <video
                 ref={videoRef}
                 class="video-js vjs-theme-fantasy w-full h-full object-contain"
                 controls
                 preload="auto"
                 src={data()}
+                onError={handleZipMediaError}
               />
             </Match>
             <Match when={props.viewing?.file_type === 'Zip'}>
               <img
                 class="w-full h-full object-contain"
-                src={`data:image/jpeg;base64,${data()}`}
+                src={data()}
+                onError={handleZipMediaError}
                 style={{
                   transform: `scale(${imageScale()}) translate(${
                     position().x
@@ -187,6 +263,7 @@ export const ImageCanvas: Component<Props> = (props) => {
                   position: 'absolute',
                   left: '0',
                   top: '0',
+                  'image-orientation': 'from-image',
                 }}
               />
             </Match>
@@ -195,7 +272,7 @@ export const ImageCanvas: Component<Props> = (props) => {
         <Show
           when={
             props.viewing?.file_type === 'Image' ||
-            props.viewing?.file_type === 'Zip'
+            (props.viewing?.file_type === 'Zip' && !isVideo())
           }
         >
           <div class="fixed bottom-3 left-0 w-full flex justify-center gap-10">
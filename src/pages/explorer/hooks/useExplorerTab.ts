@@ -147,7 +147,10 @@ export const useExplorerTab = (tabKey: string, isActiveTab: () => boolean) => {
   };
 
   const onFolderClick = (thumb: Thumbnail) => {
-    if (thumb.thumbpath) {
+    if (thumb.is_archive) {
+      // アーカイブはフォルダとして辿らず、そのまま Viewer で開く
+      invoke('open_new_viewer_tab', { path: thumb.path });
+    } else if (thumb.thumbpath) {
       invoke('open_new_viewer_tab', { path: thumb.thumbpath });
     } else {
       invoke('change_explorer_path', {
@@ -1,12 +1,13 @@
-import { ViewerTabs } from './ViewerTabs';
+import { NavigationMode, ViewerTabs } from './ViewerTabs';
 import { ViewerTab, TabState } from './ViewerTab';
 import { getMatches } from '@tauri-apps/plugin-cli';
 import { UnlistenFn } from '@tauri-apps/api/event';
 import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
 import { invoke } from '@tauri-apps/api/core';
-import { createSignal, onCleanup, onMount } from 'solid-js';
+import { createSignal, onCleanup, onMount, Show } from 'solid-js';
 import { check } from '@tauri-apps/plugin-updater';
 import { relaunch } from '@tauri-apps/plugin-process';
+import { FormatSettings } from '../../components/FormatSettings/FormatSettings';
 const appWindow = getCurrentWebviewWindow();
 
 type ViewerState = {
@@ -14,11 +15,15 @@ type ViewerState = {
     key: string;
   };
   tabs: TabState[];
+  navigation_mode: NavigationMode;
 };
 
 const Viewer = () => {
   const [activeKey, setActiveKey] = createSignal<string>();
   const [panes, setPanes] = createSignal<TabState[]>([]);
+  const [showSettings, setShowSettings] = createSignal(false);
+  const [navigationMode, setNavigationMode] =
+    createSignal<NavigationMode>('Folder');
   let unListenRef: UnlistenFn | undefined = undefined;
 
   // Check for updates on mount (only on the first viewer window)
@@ -53,8 +58,9 @@ const Viewer = () => {
   // Use appWindow.listen to only receive events targeted at this window
   appWindow
     .listen('viewer-state-changed', (event) => {
-      const { active, tabs } = event.payload as ViewerState;
+      const { active, tabs, navigation_mode } = event.payload as ViewerState;
       setPanes(tabs);
+      setNavigationMode(navigation_mode);
       setActiveKey(active?.key);
       appWindow.setFocus();
     })
@@ -84,6 +90,10 @@ const Viewer = () => {
     invoke('remove_viewer_tab', { key: targetKey, label: appWindow.label });
   };
 
+  const changeNavigationMode = (mode: NavigationMode) => {
+    invoke('set_navigation_mode', { label: appWindow.label, mode });
+  };
+
   const openExplorer = () => {
     invoke('open_new_explorer');
   };
@@ -104,7 +114,13 @@ const Viewer = () => {
         handleOnClose={remove}
         handleOnAdd={add}
         handleOnOpenExplorer={openExplorer}
+        navigationMode={navigationMode()}
+        handleOnChangeNavigationMode={changeNavigationMode}
+        handleOnOpenSettings={() => setShowSettings(true)}
       />
+      <Show when={showSettings()}>
+        <FormatSettings onClose={() => setShowSettings(false)} />
+      </Show>
     </div>
   );
 };
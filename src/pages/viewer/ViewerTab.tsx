@@ -1,10 +1,11 @@
 import { invoke } from '@tauri-apps/api/core';
 import { listen, UnlistenFn } from '@tauri-apps/api/event';
-import { createSignal, createEffect, onCleanup, onMount } from 'solid-js';
+import { createSignal, createEffect, on, onCleanup, onMount } from 'solid-js';
 import type { Component } from 'solid-js';
 import { PathSelection } from '../../features/DirectoryTree/routes/PathSelection';
 import { ImageCanvas } from '../../features/Image/ImageCanvas';
 import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
+import type { ArchiveMetadata } from '../../features/Folder/types/ArchiveMetadata';
 const appWindow = getCurrentWebviewWindow();
 
 export type File = {
@@ -12,12 +13,16 @@ export type File = {
   file_type: string;
   path: string;
   name: string;
+  // 動画か（アーカイブ内のエントリも、設定で追加した拡張子を含めてバックエンドで判定する）
+  is_video: boolean;
 };
 
 export type Directory = {
   path: string;
   name: string;
   children: FileTree[];
+  // まだ中身を読み込んでいないアーカイブ内のアーカイブ
+  lazy: boolean;
 };
 
 export type FileTree =
@@ -34,6 +39,7 @@ export type TabState = {
   path: string;
   viewing?: File;
   tree: FileTree[];
+  metadata?: ArchiveMetadata;
 };
 
 type Props = {
@@ -45,6 +51,10 @@ type Props = {
 export const ViewerTab: Component<Props> = (props) => {
   const [viewing, setViewing] = createSignal<File | undefined>(undefined);
   const [tree, setTree] = createSignal<FileTree[]>([]);
+  const [metadata, setMetadata] = createSignal<ArchiveMetadata | undefined>(
+    undefined,
+  );
+  const [neighbors, setNeighbors] = createSignal<File[]>([]);
   let unListenTabStateRef: UnlistenFn | undefined = undefined;
   let unListenDirChangedRef: UnlistenFn | undefined = undefined;
 
@@ -56,11 +66,28 @@ export const ViewerTab: Component<Props> = (props) => {
     invoke('move_backward', { label: appWindow.label });
   };
 
+  // 親フォルダ内の隣のフォルダ（次の巻・章）に移る
+  const moveToNextFolder = () => {
+    invoke('move_to_next_folder', { label: appWindow.label });
+  };
+
+  const moveToPrevFolder = () => {
+    invoke('move_to_prev_folder', { label: appWindow.label });
+  };
+
+  // 右から左に読むアーカイブ (ComicInfo.xml の Manga = YesAndRightToLeft) は左右の操作を入れ替える
+  const moveLeft = () =>
+    metadata()?.right_to_left ? moveForward() : moveBackward();
+  const moveRight = () =>
+    metadata()?.right_to_left ? moveBackward() : moveForward();
+
   const handleOnKeyDown = (event: KeyboardEvent) => {
     if (!props.isActiveTab) return;
     event.preventDefault();
-    if (event.key === 'ArrowLeft') moveBackward();
-    else if (event.key === 'ArrowRight') moveForward();
+    if (event.key === 'ArrowLeft') moveLeft();
+    else if (event.key === 'ArrowRight') moveRight();
+    else if (event.key === 'PageDown') moveToNextFolder();
+    else if (event.key === 'PageUp') moveToPrevFolder();
   };
 
   const handleOnButtonDown = (event: MouseEvent) => {
@@ -82,10 +109,11 @@ export const ViewerTab: Component<Props> = (props) => {
     unListenTabStateRef = await appWindow.listen(
       'viewer-tab-state-changed',
       (event) => {
-        const { key, viewing, tree } = event.payload as TabState;
+        const { key, viewing, tree, metadata } = event.payload as TabState;
         if (key !== props.initialTabKey) return;
         setViewing(viewing);
         setTree(tree);
+        setMetadata(metadata);
       },
     );
 
@@ -124,6 +152,15 @@ export const ViewerTab: Component<Props> = (props) => {
     });
   });
 
+  // アーカイブ内のアーカイブを開いたときに中身を読み込む
+  const expandDirectory = (tabKey: string, path: string) => {
+    invoke('expand_viewer_directory', {
+      tabKey: tabKey,
+      path: path,
+      label: appWindow.label,
+    });
+  };
+
   const changeViewing = (tabKey: string, file: File) => {
     invoke('change_viewing', {
       tabKey: tabKey,
@@ -132,6 +169,19 @@ export const ViewerTab: Component<Props> = (props) => {
     });
   };
 
+  // 表示中のファイルが変わったら前後の画像を取得する（ImageCanvas が先にデコードしておく）
+  createEffect(
+    on(viewing, async (currentViewing) => {
+      if (!currentViewing) return;
+      const files = await invoke<File[]>('get_neighbor_images', {
+        tabKey: props.initialTabKey,
+        label: appWindow.label,
+      }).catch(() => []);
+      // 取得中に別のファイルに移っていれば古い結果は使わない
+      if (viewing()?.key === currentViewing.key) setNeighbors(files);
+    }),
+  );
+
   // 閲覧履歴を記録する (Phase 2: リコメンド基盤)
   createEffect(() => {
     const currentViewing = viewing();
@@ -153,13 +203,15 @@ export const ViewerTab: Component<Props> = (props) => {
     <div class="flex h-full flex-row">
       <ImageCanvas
         viewing={viewing()}
-        moveForward={moveForward}
-        moveBackward={moveBackward}
+        neighbors={neighbors()}
+        moveForward={moveRight}
+        moveBackward={moveLeft}
       />
       <PathSelection
         viewing={viewing()}
         tree={tree()}
         onSelectedChanged={(file) => changeViewing(props.initialTabKey, file)}
+        onExpand={(path) => expandDirectory(props.initialTabKey, path)}
       />
     </div>
   );
@@ -1,6 +1,20 @@
 import { For } from 'solid-js';
 import type { JSX } from 'solid-js';
-import { FaSolidXmark, FaSolidFolderOpen, FaSolidImages } from 'solid-icons/fa';
+import {
+  FaSolidXmark,
+  FaSolidFolderOpen,
+  FaSolidImages,
+  FaSolidGear,
+} from 'solid-icons/fa';
+
+// バックエンドの viewer_state::NavigationMode
+export type NavigationMode = 'Folder' | 'TreeWrap' | 'TreeStop';
+
+const NAVIGATION_MODES: { mode: NavigationMode; label: string }[] = [
+  { mode: 'Folder', label: 'フォルダ内' },
+  { mode: 'TreeWrap', label: 'ツリー全体（ループ）' },
+  { mode: 'TreeStop', label: 'ツリー全体（端で停止）' },
+];
 
 type TabInfo<T> = T & {
   key: string;
@@ -15,6 +29,10 @@ type Props<T> = {
   handleOnClose: (key: string) => void;
   handleOnAdd: () => void;
   handleOnOpenExplorer: () => void;
+  // ページ送りでのファイルのたどり方
+  navigationMode: NavigationMode;
+  handleOnChangeNavigationMode: (mode: NavigationMode) => void;
+  handleOnOpenSettings: () => void;
 };
 
 export const ViewerTabs = <T,>(props: Props<T>) => {
@@ -62,6 +80,26 @@ export const ViewerTabs = <T,>(props: Props<T>) => {
         >
           <FaSolidFolderOpen class="ml-0.5 h-5 w-5" />
         </div>
+        <select
+          class="ml-auto h-8 shrink-0 rounded border-2 border-neutral-500 bg-neutral-900 px-1 text-sm text-neutral-400 transition-colors hover:bg-neutral-700 hover:text-neutral-300"
+          title="ページ送り"
+          value={props.navigationMode}
+          onChange={(e) =>
+            props.handleOnChangeNavigationMode(
+              e.currentTarget.value as NavigationMode,
+            )
+          }
+        >
+          <For each={NAVIGATION_MODES}>
+            {({ mode, label }) => <option value={mode}>{label}</option>}
+          </For>
+        </select>
+        <div
+          class="mx-1 flex h-8 w-8 shrink-0 flex-col items-center justify-center rounded-full border-2 border-neutral-500 bg-neutral-900 text-neutral-400 transition-colors hover:bg-neutral-700 hover:text-neutral-300"
+          onClick={() => props.handleOnOpenSettings()}
+        >
+          <FaSolidGear class="h-4 w-4" />
+        </div>
       </div>
       <div class="relative" style={{ height: 'calc(100% - 2rem)' }}>
         <For each={props.tabs}>